@@ -0,0 +1,190 @@
+use crate::scene::Scene;
+use cgmath::Vector3;
+use std::ops::{Add, Mul, Sub};
+use std::path::{Path, PathBuf};
+
+// Keyframed animation of cameras and spheres.
+//
+// Every animated property has its own track of (time in seconds, value) keys, sorted by
+// time. Before the first key and after the last one the value holds still.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    // Straight lines between keys
+    Linear,
+    // A curve through the keys with no sudden changes in speed, easing in and out
+    // at the first and last key
+    Smooth,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    pub keys: Vec<(f32, T)>,
+    pub interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn sample(&self, time: f32) -> T {
+        let keys = &self.keys;
+        let (first, last) = (keys[0], keys[keys.len() - 1]);
+        if time <= first.0 {
+            return first.1;
+        }
+        if time >= last.0 {
+            return last.1;
+        }
+
+        // The segment from key k to key k + 1 contains the time
+        let k = keys.iter().position(|(t, _)| *t > time).unwrap() - 1;
+        let (t0, p0) = keys[k];
+        let (t1, p1) = keys[k + 1];
+        let dt = t1 - t0;
+        let s = (time - t0) / dt;
+
+        match self.interpolation {
+            Interpolation::Linear => p0 + (p1 - p0) * s,
+            Interpolation::Smooth => {
+                // Cubic Hermite curve with Catmull-Rom tangents, scaled to the segment
+                // length. The tangents at the first and last key are zero.
+                let zero = p0 * 0.0;
+                let m0 = match k.checked_sub(1).map(|i| keys[i]) {
+                    Some((t, p)) => (p1 - p) * (dt / (t1 - t)),
+                    None => zero,
+                };
+                let m1 = match keys.get(k + 2) {
+                    Some((t, p)) => (*p - p0) * (dt / (t - t0)),
+                    None => zero,
+                };
+
+                let (s2, s3) = (s * s, s * s * s);
+                p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + m0 * (s3 - 2.0 * s2 + s)
+                    + p1 * (3.0 * s2 - 2.0 * s3)
+                    + m1 * (s3 - s2)
+            }
+        }
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keys[self.keys.len() - 1].0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraAnimation {
+    // Index into Scene::cameras
+    pub camera: usize,
+    pub pos: Option<Track<Vector3<f32>>>,
+    pub rot: Option<Track<Vector3<f32>>>,
+    pub fov: Option<Track<f32>>,
+    pub focal_length: Option<Track<f32>>,
+    pub aperture_radius: Option<Track<f32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SphereAnimation {
+    // Index into Scene::spheres
+    pub sphere: usize,
+    pub pos: Track<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Animation {
+    pub cameras: Vec<CameraAnimation>,
+    pub spheres: Vec<SphereAnimation>,
+}
+
+impl Animation {
+    pub fn is_empty(&self) -> bool {
+        self.cameras.is_empty() && self.spheres.is_empty()
+    }
+
+    // Time of the last key of any track
+    pub fn duration(&self) -> f32 {
+        let mut end: f32 = 0.0;
+        for camera in &self.cameras {
+            for track in [&camera.pos, &camera.rot].iter().copied().flatten() {
+                end = end.max(track.end());
+            }
+            let floats = [&camera.fov, &camera.focal_length, &camera.aperture_radius];
+            for track in floats.iter().copied().flatten() {
+                end = end.max(track.end());
+            }
+        }
+        for sphere in &self.spheres {
+            end = end.max(sphere.pos.end());
+        }
+        end
+    }
+
+    // Whether the focal length of a camera is keyed, in which case it shouldn't be autofocused
+    pub fn animates_focal_length(&self, camera: usize) -> bool {
+        self.cameras
+            .iter()
+            .any(|animation| animation.camera == camera && animation.focal_length.is_some())
+    }
+
+    // Move the scene to where it is at the given time
+    pub fn apply(&self, scene: &mut Scene, time: f32) {
+        for animation in &self.cameras {
+            let camera = &mut scene.cameras[animation.camera];
+            if let Some(track) = &animation.pos {
+                camera.pos = track.sample(time);
+            }
+            if let Some(track) = &animation.rot {
+                camera.rot = track.sample(time);
+            }
+            if let Some(track) = &animation.fov {
+                camera.fov = track.sample(time);
+            }
+            if let Some(track) = &animation.focal_length {
+                camera.focal_length = track.sample(time);
+            }
+            if let Some(track) = &animation.aperture_radius {
+                camera.aperture_radius = track.sample(time);
+            }
+        }
+
+        for animation in &self.spheres {
+            scene.spheres[animation.sphere].pos = animation.pos.sample(time);
+        }
+
+        // Wormholes lead to wherever their other end is now
+        if !self.spheres.is_empty() {
+            for i in 0..scene.spheres.len() {
+                let params = &scene.spheres[i].material.wormhole_params;
+                if !params.is_wormhole {
+                    continue;
+                }
+                let other_end = params.other_end_object_id;
+                if let Some(other) = scene.spheres.iter().find(|s| s.object_id == other_end) {
+                    let offset = other.pos - scene.spheres[i].pos;
+                    scene.spheres[i].material.wormhole_params.wormhole_offset = offset;
+                }
+            }
+        }
+    }
+}
+
+// The file name for a frame of an image sequence. The last run of '#' in the file name
+// is replaced by the zero-padded frame number, as in "frames/shot_####.png". Without
+// any '#' the number goes before the extension, so "shot.png" gives "shot_0001.png".
+pub fn frame_path(pattern: &Path, frame: u32) -> PathBuf {
+    let file_name = pattern.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+
+    let file_name = match file_name.rfind('#') {
+        Some(end) => {
+            let start = file_name[..end].trim_end_matches('#').len();
+            let width = end + 1 - start;
+            format!("{}{:0width$}{}", &file_name[..start], frame, &file_name[end + 1..], width = width)
+        }
+        None => match file_name.rfind('.') {
+            Some(dot) => format!("{}_{:04}{}", &file_name[..dot], frame, &file_name[dot..]),
+            None => format!("{}_{:04}", file_name, frame),
+        },
+    };
+    pattern.with_file_name(file_name)
+}
@@ -0,0 +1,153 @@
+use crate::hdr::read_hdr;
+use crate::helpers::{clamp, rad};
+use crate::image::read_pgm;
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// The shape of the opening of a camera's lens, which is the shape out of focus highlights
+// take. Shapes are sized to the unit disk, which the aperture radius scales.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aperture {
+    Circle,
+    // A polygon of straight blades with their corners on the circle, rounded towards the
+    // circle by a curvature from 0 to 1. A corner is at the top, turned counterclockwise by
+    // a rotation in degrees.
+    Blades { count: u32, rotation: f32, curvature: f32 },
+    // Any shape, from a grayscale image of how much light each part of the lens lets through
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    // A point on the aperture for two random numbers from 0 to 1. The points are spread
+    // evenly over the opening, without any being thrown away.
+    pub fn sample(&self, u1: f32, u2: f32) -> (f32, f32) {
+        let (x, y) = match self {
+            Aperture::Circle => {
+                let angle = u1 * PI * 2.0;
+                let length = u2.sqrt();
+                return (length * angle.cos(), length * angle.sin());
+            }
+            Aperture::Blades {
+                count,
+                rotation,
+                curvature,
+            } => sample_blades(*count, *rotation, *curvature, u1, u2),
+            Aperture::Mask(mask) => mask.sample(u1, u2),
+        };
+        // Rays cross over at the focus, so turn the shape around for out of focus highlights
+        // in the background to look like the shape as given
+        (-x, -y)
+    }
+}
+
+// Each blade is the edge of a wedge from the middle. Pick one half of a wedge with u1, and
+// the angle within it with what is left of u1, in proportion to the area up to that angle.
+fn sample_blades(count: u32, rotation: f32, curvature: f32, u1: f32, u2: f32) -> (f32, f32) {
+    let halves = 2.0 * count as f32;
+    let half_angle = PI / count as f32;
+    let half = (u1 * halves).floor().min(halves - 1.0);
+    let u = u1 * halves - half;
+
+    // The edge is a blend of the straight blade, at the apothem from the middle, and the circle
+    let apothem = half_angle.cos() * (1.0 - curvature);
+    let edge = |angle: f32| apothem / angle.cos() + curvature;
+    // The area between the middle of the blade and an angle, times two
+    let area = |angle: f32| {
+        apothem * apothem * angle.tan()
+            + 2.0 * apothem * curvature * (1.0 / angle.cos() + angle.tan()).ln()
+            + curvature * curvature * angle
+    };
+
+    // Straight blades have a closed form, which is close enough for Newton's method to
+    // finish off curved ones in a couple of steps
+    let target = u * area(half_angle);
+    let mut angle = (u * half_angle.tan()).atan();
+    if curvature > 0.0 {
+        for _ in 0..2 {
+            angle -= (area(angle) - target) / (edge(angle) * edge(angle));
+        }
+    }
+    let angle = clamp(angle, 0.0, half_angle);
+
+    let length = edge(angle) * u2.sqrt();
+    let side = if (half / 2.0).fract() == 0.0 { -angle } else { angle };
+    // The first wedge has its corner at the top
+    let angle = (half / 2.0).floor() * 2.0 * half_angle + side + PI / 2.0 + half_angle + rad(rotation);
+    (length * angle.cos(), length * angle.sin())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ApertureMask {
+    pub width: usize,
+    pub height: usize,
+    // How much light each pixel lets through, top row first
+    pub transmission: Vec<f32>,
+    // Running total of the transmission, to pick pixels in proportion to it
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    pub fn new(width: usize, height: usize, transmission: Vec<f32>) -> Result<ApertureMask, String> {
+        if width * height == 0 || transmission.len() != width * height {
+            return Err("the aperture mask has no pixels".into());
+        }
+        let mut total = 0.0;
+        let cdf: Vec<f32> = transmission
+            .iter()
+            .map(|value| {
+                total += value.max(0.0);
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return Err("the aperture mask lets no light through".into());
+        }
+        Ok(ApertureMask {
+            width,
+            height,
+            transmission,
+            cdf,
+        })
+    }
+
+    // A grayscale .pgm image, or the brightness of a .hdr image
+    pub fn load(path: &Path) -> io::Result<ApertureMask> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        let (width, height, transmission) = match extension.as_deref() {
+            Some("pgm") => read_pgm(path)?,
+            Some("hdr") => {
+                let image = read_hdr(path)?;
+                let transmission = image.pixels.iter().map(|col| col.luminance()).collect();
+                (image.width, image.height, transmission)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "aperture masks are .pgm or .hdr images",
+                ))
+            }
+        };
+        ApertureMask::new(width, height, transmission).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Pick a pixel with u1 and a point within it with what is left of u1 and u2. The image
+    // is centered on the lens, with its longer side across the disk and its top row on top.
+    fn sample(&self, u1: f32, u2: f32) -> (f32, f32) {
+        let total = self.cdf[self.cdf.len() - 1];
+        let target = u1 * total;
+        let i = self.cdf.partition_point(|&sum| sum <= target).min(self.cdf.len() - 1);
+        let start = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        let share = self.cdf[i] - start;
+        let within = if share > 0.0 { clamp((target - start) / share, 0.0, 1.0) } else { 0.5 };
+
+        let x = (i % self.width) as f32 + within;
+        let y = (i / self.width) as f32 + u2;
+        let scale = 2.0 / self.width.max(self.height) as f32;
+        (
+            (x - self.width as f32 / 2.0) * scale,
+            (self.height as f32 / 2.0 - y) * scale,
+        )
+    }
+}
@@ -1,14 +1,32 @@
+use crate::render::Pass;
 use std::time::{Duration, Instant};
 
 pub struct Viewport {
     pub overlays_enabled: bool,
+    // Draw the overlays as if the lens had no distortion, instead of lined up with the render
+    pub undistorted_overlays: bool,
     pub autofocus: bool,
     pub depth_pass: bool,
     pub normal_pass: bool,
+    // Whether the camera path is being recorded
+    pub recording: bool,
+    pub chromatic_aberration_strength: f32,
     pub sample_iter: u32,
     pub time: Time,
 }
 
+impl Viewport {
+    pub fn pass(&self) -> Pass {
+        if self.depth_pass {
+            Pass::Depth
+        } else if self.normal_pass {
+            Pass::Normal
+        } else {
+            Pass::Beauty
+        }
+    }
+}
+
 pub struct Time {
     pub start: Instant,
     pub sum: Duration,
@@ -25,17 +43,19 @@ pub fn timestamp() -> Instant {
     // millis
 }
 
-pub fn update_time(window: &mut minifb::Window, time: &mut Time, sample_iter: &u32) {
+// Returns a new window title every 30 frames
+pub fn update_time(time: &mut Time, sample_iter: &u32) -> Option<String> {
     time.framecount += 1;
     let now = timestamp();
+    let mut title = None;
 
     if time.framecount % 30 == 0 {
         let diff = &(now - time.prev).as_millis().to_string()[..];
         let iterations = &(sample_iter).to_string()[..];
-        window
-        .set_title(&("ms: ".to_owned() + diff + &"   iterations: ".to_owned() + iterations)[..]);
+        title = Some("ms: ".to_owned() + diff + "   iterations: " + iterations);
         time.sum = Duration::new(0,0);
     }
     
     time.prev = now;
+    title
 }
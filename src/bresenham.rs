@@ -1,6 +1,7 @@
 use crate::helpers::Col;
-use crate::scene::Camera;
-use cgmath::{EuclideanSpace, Matrix4, Point3, Vector2, Vector3, Vector4};
+use crate::pathtrace::project;
+use crate::scene::{Camera, Projection};
+use cgmath::{EuclideanSpace, Matrix, Matrix4, Point3, Vector2, Vector3, Vector4};
 
 pub struct Line2d {
     pub x0: i32,
@@ -82,8 +83,8 @@ impl Line2d {
 
 fn plot_line(
     line: Line2d,
-    display_width: &'static usize,
-    display_height: &'static usize,
+    display_width: usize,
+    display_height: usize,
 ) -> impl Iterator<Item = (i32, i32)> {
     let x0 = line.x0;
     let y0 = line.y0;
@@ -128,7 +129,7 @@ fn plot_line(
     };
     coordinates
         .filter(move |(x, y)| {
-            *x < (*display_width as i32) && *x > 0 && *y < (*display_height as i32) && *y > 0
+            *x < (display_width as i32) && *x > 0 && *y < (display_height as i32) && *y > 0
         })
         .map(move |(x, y)| {
             if (y1 - y0).abs() < (x1 - x0).abs() {
@@ -158,12 +159,19 @@ impl Line3d {
     pub fn render_line(
         &self,
         camera: &Camera,
-        display_width: &'static usize,
-        display_height: &'static usize,
+        display_width: usize,
+        display_height: usize,
     ) -> impl Iterator<Item = (i32, i32)> {
+        if camera.projection != Projection::Perspective || !camera.distortion.is_none() || camera.shifted() {
+            let points = self.render_projected(camera, display_width, display_height);
+            return either::Right(either::Right(points.into_iter()));
+        }
+
+        // The field of view is vertical, like in camera_ray, and an anamorphic lens squeezes
+        // more of the width into the image
         let matrix: Matrix4<f32> = cgmath::PerspectiveFov {
             fovy: cgmath::Rad(camera.fov * std::f32::consts::PI / 180.0),
-            aspect: 1.0,
+            aspect: display_width as f32 / display_height as f32 * camera.anamorphic_squeeze,
             near: 1.0,
             far: 10.0,
         }
@@ -187,20 +195,20 @@ impl Line3d {
         let coord1 = matrix * dir * coord1;
         let coord2 = matrix * dir * coord2;
 
-        let half_display_height = *display_height as f32 / -2.0;
-        let half_display_width = *display_width as i32 / 2;
+        let half_display_height = display_height as f32 / -2.0;
+        let half_display_width = display_width as f32 / -2.0;
 
         let line = Line2d::new(
-            (half_display_height * coord1.x / coord1.w) as i32 + half_display_width,
+            (half_display_width * coord1.x / coord1.w) as i32 + -half_display_width as i32,
             (half_display_height * coord1.y / coord1.w) as i32 + -half_display_height as i32,
-            (half_display_height * coord2.x / coord2.w) as i32 + half_display_width,
+            (half_display_width * coord2.x / coord2.w) as i32 + -half_display_width as i32,
             (half_display_height * coord2.y / coord2.w) as i32 + -half_display_height as i32,
         )
-        .clamp(*display_width, *display_height);
+        .clamp(display_width, display_height);
 
         if coord1.w > 0.0 && coord2.w > 0.0 {
             if let Some(line) = line {
-                either::Right(plot_line(line, display_width, display_height))
+                either::Right(either::Left(plot_line(line, display_width, display_height)))
             } else {
                 either::Left(std::iter::empty())
             }
@@ -208,4 +216,49 @@ impl Line3d {
             either::Left(std::iter::empty())
         }
     }
+
+    // Lines bend under the fisheye and panorama projections and lens distortion, so points
+    // along the line are projected like camera_ray would see them and joined up with short
+    // straight lines. A shifted lens moves the whole picture.
+    fn render_projected(&self, camera: &Camera, display_width: usize, display_height: usize) -> Vec<(i32, i32)> {
+        let segments = match camera.projection {
+            Projection::Orthographic if camera.distortion.is_none() => 1,
+            _ => 32,
+        };
+        let (width, height) = (display_width as f32, display_height as f32);
+
+        let rot = cgmath::Matrix4::from_angle_z(cgmath::Rad(camera.rot.z))
+            * cgmath::Matrix4::from_angle_y(cgmath::Rad(camera.rot.y))
+            * cgmath::Matrix4::from_angle_x(cgmath::Rad(camera.rot.x));
+        let to_camera = rot.transpose();
+
+        let screen_point = |t: f32| {
+            let point = self.p1 + (self.p2 - self.p1) * t;
+            let point = (to_camera * (point - camera.pos).extend(0.0)).truncate();
+            let squeeze = camera.anamorphic_squeeze;
+            project(camera, point, width * squeeze / height).map(|(x, y)| {
+                let (x, y) = camera.distortion.distort(x / squeeze, y);
+                let (x, y) = (x - camera.shift.x, y - camera.shift.y);
+                // Mirrored horizontally, like the perspective lines, for Wireframe::render
+                (width / 2.0 + 1.0 - x * height, height / 2.0 - 1.0 - y * height)
+            })
+        };
+
+        let mut pixels = vec![];
+        let mut previous = screen_point(0.0);
+        for segment in 1..=segments {
+            let next = screen_point(segment as f32 / segments as f32);
+            if let (Some(p1), Some(p2)) = (previous, next) {
+                // Segments crossing the seam of a panorama would span the whole image
+                if (p1.0 - p2.0).abs() < width / 2.0 {
+                    let line = Line2d::new(p1.0 as i32, p1.1 as i32, p2.0 as i32, p2.1 as i32);
+                    if let Some(line) = line.clamp(display_width, display_height) {
+                        pixels.extend(plot_line(line, display_width, display_height));
+                    }
+                }
+            }
+            previous = next;
+        }
+        pixels
+    }
 }
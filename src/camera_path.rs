@@ -0,0 +1,133 @@
+use crate::animation::{CameraAnimation, Interpolation, Track};
+use crate::scene::Camera;
+use cgmath::Vector3;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// The camera state of one viewer frame, at a time in seconds since recording started
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathFrame {
+    pub time: f32,
+    pub pos: Vector3<f32>,
+    pub rot: Vector3<f32>,
+    pub fov: f32,
+    pub focal_length: f32,
+    pub aperture_radius: f32,
+}
+
+// A camera flight recorded in the viewer, stored as text with one frame per line:
+//
+//     # time pos_x pos_y pos_z rot_x rot_y rot_z fov focal_length aperture_radius
+//     0 10 -10 10 -0.6 0 0.8 90 17.3 0.1
+//     0.041 10 -9.8 10 -0.6 0 0.8 90 17.1 0.1
+//
+// Played back, the camera moves in straight lines between the recorded frames.
+#[derive(Debug, Clone, Default)]
+pub struct CameraPath {
+    pub frames: Vec<PathFrame>,
+}
+
+impl CameraPath {
+    // Add the state of the camera at the given time, which must be later than the last frame's
+    pub fn record(&mut self, time: f32, camera: &Camera) {
+        if self.frames.last().is_some_and(|last| time <= last.time) {
+            return;
+        }
+        self.frames.push(PathFrame {
+            time,
+            pos: camera.pos,
+            rot: camera.rot,
+            fov: camera.fov,
+            focal_length: camera.focal_length,
+            aperture_radius: camera.aperture_radius,
+        });
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |last| last.time)
+    }
+
+    pub fn parse(source: &str) -> Result<CameraPath, String> {
+        let mut path = CameraPath::default();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse().map_err(|_| error(format!("invalid number '{}'", value))))
+                .collect::<Result<Vec<f32>, String>>()?;
+            if values.len() != 10 {
+                return Err(error(format!("expected 10 numbers, found {}", values.len())));
+            }
+            if path.frames.last().is_some_and(|last| values[0] <= last.time) {
+                return Err(error("times must increase from frame to frame".into()));
+            }
+
+            path.frames.push(PathFrame {
+                time: values[0],
+                pos: Vector3::new(values[1], values[2], values[3]),
+                rot: Vector3::new(values[4], values[5], values[6]),
+                fov: values[7],
+                focal_length: values[8],
+                aperture_radius: values[9],
+            });
+        }
+
+        if path.frames.is_empty() {
+            return Err("the camera path has no frames".into());
+        }
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<CameraPath> {
+        let source = fs::read_to_string(path)?;
+        CameraPath::parse(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# time pos_x pos_y pos_z rot_x rot_y rot_z fov focal_length aperture_radius")?;
+        for frame in &self.frames {
+            writeln!(
+                file,
+                "{} {} {} {} {} {} {} {} {} {}",
+                frame.time,
+                frame.pos.x,
+                frame.pos.y,
+                frame.pos.z,
+                frame.rot.x,
+                frame.rot.y,
+                frame.rot.z,
+                frame.fov,
+                frame.focal_length,
+                frame.aperture_radius
+            )?;
+        }
+        file.flush()
+    }
+
+    // The path as an animation of a scene camera, keyed at every recorded frame
+    pub fn animation(&self, camera: usize) -> CameraAnimation {
+        fn track<T>(frames: &[PathFrame], value: impl Fn(&PathFrame) -> T) -> Option<Track<T>> {
+            Some(Track {
+                keys: frames.iter().map(|frame| (frame.time, value(frame))).collect(),
+                interpolation: Interpolation::Linear,
+            })
+        }
+
+        CameraAnimation {
+            camera,
+            pos: track(&self.frames, |frame| frame.pos),
+            rot: track(&self.frames, |frame| frame.rot),
+            fov: track(&self.frames, |frame| frame.fov),
+            focal_length: track(&self.frames, |frame| frame.focal_length),
+            aperture_radius: track(&self.frames, |frame| frame.aperture_radius),
+        }
+    }
+}
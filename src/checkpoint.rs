@@ -0,0 +1,370 @@
+use crate::aperture::Aperture;
+use crate::distortion::Distortion;
+use crate::helpers::{Col, ObjectID};
+use crate::progress::StopConditions;
+use crate::render::{Pass, RenderSettings};
+use crate::scene::{Camera, Material, Projection, Scene, Stereo};
+use cgmath::{Vector2, Vector3};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// The header names its layout, and changes to the fields bump the version. Every field
+// is required, so checkpoints from other versions are refused rather than half read.
+const MAGIC: &str = "raytracer checkpoint 3";
+
+// Everything needed to continue a render where it left off.
+//
+// The random numbers of every sample are derived from (seed, pixel, sample index),
+// so the seed and sample count are all the RNG state there is. The scene itself is
+// not stored, only a hash of it, to catch resuming against a scene that changed.
+//
+// On disk it's a text header of "key value" lines ending in "end", followed by the
+// accumulation and even-sample buffers as little-endian f32 RGB in display order.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub settings: RenderSettings,
+    pub pass: Pass,
+    pub samples: u32,
+    // What the render was started to run until, with the time budget cut down to what's left
+    pub stop: StopConditions,
+    pub scene_hash: u64,
+    pub camera: Camera,
+    pub render_buffer: Vec<Col>,
+    // Sum of the even-numbered samples, for the noise estimate
+    pub half_buffer: Vec<Col>,
+}
+
+impl Checkpoint {
+    // Write to a temporary file first, so a crash while saving keeps the previous checkpoint
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        let settings = &self.settings;
+        let camera = &self.camera;
+
+        writeln!(file, "{}", MAGIC)?;
+        writeln!(file, "width {}", settings.width)?;
+        writeln!(file, "height {}", settings.height)?;
+        writeln!(file, "bounces {}", settings.max_bounces)?;
+        writeln!(file, "wormhole_bounces {}", settings.max_wormhole_bounces)?;
+        writeln!(file, "chromatic_aberration {}", settings.chromatic_aberration_strength)?;
+        writeln!(file, "spectral {}", settings.spectral)?;
+        writeln!(file, "seed {}", settings.seed)?;
+        writeln!(file, "pass {}", pass_name(self.pass))?;
+        writeln!(file, "samples {}", self.samples)?;
+        let stop = &self.stop;
+        writeln!(file, "stop_samples {}", optional(stop.samples))?;
+        writeln!(file, "stop_time {}", optional(stop.time_budget.map(|budget| budget.as_secs_f32())))?;
+        writeln!(file, "stop_noise {}", optional(stop.noise_threshold))?;
+        writeln!(file, "scene_hash {:016x}", self.scene_hash)?;
+        writeln!(file, "camera_pos {} {} {}", camera.pos.x, camera.pos.y, camera.pos.z)?;
+        writeln!(file, "camera_rot {} {} {}", camera.rot.x, camera.rot.y, camera.rot.z)?;
+        writeln!(file, "camera_fov {}", camera.fov)?;
+        writeln!(file, "camera_focal_length {}", camera.focal_length)?;
+        writeln!(file, "camera_aperture_radius {}", camera.aperture_radius)?;
+        writeln!(file, "camera_anamorphic_squeeze {}", camera.anamorphic_squeeze)?;
+        let distortion = &camera.distortion;
+        writeln!(
+            file,
+            "camera_distortion {} {} {} {} {}",
+            distortion.k1, distortion.k2, distortion.k3, distortion.p1, distortion.p2
+        )?;
+        writeln!(file, "camera_vignetting {}", camera.vignetting)?;
+        writeln!(file, "camera_optical_vignetting {}", camera.optical_vignetting)?;
+        writeln!(file, "camera_shift {} {}", camera.shift.x, camera.shift.y)?;
+        writeln!(file, "camera_tilt {} {}", camera.tilt.x, camera.tilt.y)?;
+        writeln!(file, "camera_projection {}", camera.projection.name())?;
+        writeln!(file, "camera_ortho_height {}", camera.ortho_height)?;
+        writeln!(file, "camera_stereo {}", camera.stereo.name())?;
+        writeln!(file, "camera_interocular_distance {}", camera.interocular_distance)?;
+        writeln!(file, "camera_convergence {}", camera.convergence)?;
+        writeln!(file, "end")?;
+
+        for col in self.render_buffer.iter().chain(&self.half_buffer) {
+            file.write_all(&col.r.to_le_bytes())?;
+            file.write_all(&col.g.to_le_bytes())?;
+            file.write_all(&col.b.to_le_bytes())?;
+        }
+        file.flush()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut header = vec![];
+        loop {
+            let mut line = String::new();
+            if file.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of file in the header"));
+            }
+            let line = line.trim_end();
+            if line == "end" {
+                break;
+            }
+            header.push(line.to_string());
+        }
+        match header.first() {
+            Some(line) if line == MAGIC => (),
+            Some(line) if line.starts_with("raytracer checkpoint ") => {
+                return Err(invalid_data(&format!("unsupported checkpoint version, expected '{}'", MAGIC)))
+            }
+            _ => return Err(invalid_data("not a checkpoint file")),
+        }
+
+        let field = |key: &str| -> io::Result<Vec<&str>> {
+            header
+                .iter()
+                .find_map(|line| {
+                    let mut words = line.split_whitespace();
+                    if words.next() == Some(key) {
+                        Some(words.collect())
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| invalid_data(&format!("missing {}", key)))
+        };
+        let value = |key: &str| -> io::Result<&str> {
+            field(key)?
+                .first()
+                .copied()
+                .ok_or_else(|| invalid_data(&format!("missing value for {}", key)))
+        };
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> io::Result<T> {
+            value
+                .parse()
+                .map_err(|_| invalid_data(&format!("invalid value '{}' for {}", value, key)))
+        }
+        let number = |key: &str| -> io::Result<f32> { parse(key, value(key)?) };
+        // Unset stop conditions are written as "none"
+        let optional = |key: &str| -> io::Result<Option<&str>> {
+            match value(key)? {
+                "none" => Ok(None),
+                value => Ok(Some(value)),
+            }
+        };
+        let pair = |key: &str| -> io::Result<Vector2<f32>> {
+            match &field(key)?[..] {
+                [x, y] => Ok(Vector2::new(parse(key, x)?, parse(key, y)?)),
+                _ => Err(invalid_data(&format!("{} needs 2 values", key))),
+            }
+        };
+        let vector = |key: &str| -> io::Result<Vector3<f32>> {
+            match &field(key)?[..] {
+                [x, y, z] => Ok(Vector3::new(parse(key, x)?, parse(key, y)?, parse(key, z)?)),
+                _ => Err(invalid_data(&format!("{} needs 3 values", key))),
+            }
+        };
+
+        let settings = RenderSettings {
+            width: parse("width", value("width")?)?,
+            height: parse("height", value("height")?)?,
+            max_bounces: parse("bounces", value("bounces")?)?,
+            max_wormhole_bounces: parse("wormhole_bounces", value("wormhole_bounces")?)?,
+            chromatic_aberration_strength: number("chromatic_aberration")?,
+            spectral: parse("spectral", value("spectral")?)?,
+            seed: parse("seed", value("seed")?)?,
+        };
+        let pass = match value("pass")? {
+            "beauty" => Pass::Beauty,
+            "depth" => Pass::Depth,
+            "normal" => Pass::Normal,
+            pass => return Err(invalid_data(&format!("unknown render pass '{}'", pass))),
+        };
+        let stop = StopConditions {
+            samples: optional("stop_samples")?.map(|value| parse("stop_samples", value)).transpose()?,
+            time_budget: optional("stop_time")?
+                .map(|value| {
+                    Duration::try_from_secs_f32(parse("stop_time", value)?)
+                        .map_err(|_| invalid_data(&format!("invalid value '{}' for stop_time", value)))
+                })
+                .transpose()?,
+            noise_threshold: optional("stop_noise")?.map(|value| parse("stop_noise", value)).transpose()?,
+        };
+        let scene_hash = u64::from_str_radix(value("scene_hash")?, 16)
+            .map_err(|_| invalid_data("invalid scene_hash"))?;
+        let name = value("camera_projection")?;
+        let projection = Projection::from_name(name)
+            .ok_or_else(|| invalid_data(&format!("unknown projection '{}'", name)))?;
+        let name = value("camera_stereo")?;
+        let stereo = Stereo::from_name(name)
+            .ok_or_else(|| invalid_data(&format!("unknown stereo layout '{}'", name)))?;
+        let distortion = match field("camera_distortion")?[..] {
+            [k1, k2, k3, p1, p2] => Distortion {
+                k1: parse("camera_distortion", k1)?,
+                k2: parse("camera_distortion", k2)?,
+                k3: parse("camera_distortion", k3)?,
+                p1: parse("camera_distortion", p1)?,
+                p2: parse("camera_distortion", p2)?,
+            },
+            _ => return Err(invalid_data("camera_distortion needs 5 values")),
+        };
+        let camera = Camera {
+            name: None,
+            pos: vector("camera_pos")?,
+            rot: vector("camera_rot")?,
+            fov: number("camera_fov")?,
+            focal_length: number("camera_focal_length")?,
+            aperture_radius: number("camera_aperture_radius")?,
+            // The shape is part of the scene hash, and comes from the scene
+            aperture: Aperture::Circle,
+            anamorphic_squeeze: number("camera_anamorphic_squeeze")?,
+            distortion,
+            vignetting: number("camera_vignetting")?,
+            optical_vignetting: number("camera_optical_vignetting")?,
+            shift: pair("camera_shift")?,
+            tilt: pair("camera_tilt")?,
+            projection,
+            ortho_height: number("camera_ortho_height")?,
+            stereo,
+            interocular_distance: number("camera_interocular_distance")?,
+            convergence: number("camera_convergence")?,
+            object_id: ObjectID::from(0),
+        };
+
+        // Two buffers of 12 byte pixels, for an image size straight from the file
+        let pixels = settings
+            .width
+            .checked_mul(settings.height)
+            .filter(|pixels| pixels.checked_mul(2 * 12).is_some())
+            .ok_or_else(|| invalid_data("the image size is too large"))?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        if data.len() != pixels * 2 * 12 {
+            return Err(invalid_data("the pixel data doesn't match the image size"));
+        }
+        let mut cols = data.chunks_exact(12).map(|bytes| {
+            let channel = |i: usize| {
+                f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+            };
+            Col::new(channel(0), channel(4), channel(8))
+        });
+
+        Ok(Checkpoint {
+            settings,
+            pass,
+            samples: parse("samples", value("samples")?)?,
+            stop,
+            scene_hash,
+            camera,
+            render_buffer: cols.by_ref().take(pixels).collect(),
+            half_buffer: cols.collect(),
+        })
+    }
+}
+
+fn pass_name(pass: Pass) -> &'static str {
+    match pass {
+        Pass::Beauty => "beauty",
+        Pass::Depth => "depth",
+        Pass::Normal => "normal",
+    }
+}
+
+fn optional<T: std::fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// A hash of everything in the scene that affects the image, apart from the cameras.
+// FNV-1a, so the value stays the same across builds and Rust versions.
+pub fn scene_hash(scene: &Scene) -> u64 {
+    let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+
+    for sphere in &scene.spheres {
+        hash.vector(sphere.pos);
+        hash.f32(sphere.radius);
+        hash.material(&sphere.material);
+        hash.bytes(sphere.object_id.to_string().as_bytes());
+    }
+
+    for col in &scene.sky.colors {
+        hash.col(*col);
+    }
+    hash.f32(scene.sky.intensity);
+    if let Some(environment) = &scene.sky.environment {
+        hash.bytes(&(environment.width as u64).to_le_bytes());
+        hash.bytes(&(environment.height as u64).to_le_bytes());
+        for col in &environment.pixels {
+            hash.col(*col);
+        }
+    }
+
+    // The checkpoint holds the camera, except for the shape of its aperture
+    match &scene.camera().aperture {
+        Aperture::Circle => hash.bytes(&[0]),
+        Aperture::Blades {
+            count,
+            rotation,
+            curvature,
+        } => {
+            hash.bytes(&[1]);
+            hash.bytes(&count.to_le_bytes());
+            hash.f32(*rotation);
+            hash.f32(*curvature);
+        }
+        Aperture::Mask(mask) => {
+            hash.bytes(&[2]);
+            hash.bytes(&(mask.width as u64).to_le_bytes());
+            hash.bytes(&(mask.height as u64).to_le_bytes());
+            for value in &mask.transmission {
+                hash.f32(*value);
+            }
+        }
+    }
+
+    hash.0
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_bits().to_le_bytes());
+    }
+
+    fn vector(&mut self, v: Vector3<f32>) {
+        self.f32(v.x);
+        self.f32(v.y);
+        self.f32(v.z);
+    }
+
+    fn col(&mut self, col: Col) {
+        self.f32(col.r);
+        self.f32(col.g);
+        self.f32(col.b);
+    }
+
+    fn material(&mut self, material: &Material) {
+        self.col(material.color);
+        self.f32(material.metallic);
+        self.f32(material.roughness);
+        self.col(material.emission_color);
+        self.f32(material.emission_intensity);
+        let wormhole = &material.wormhole_params;
+        self.bytes(&[wormhole.is_wormhole as u8]);
+        self.vector(wormhole.wormhole_offset);
+        self.bytes(wormhole.other_end_object_id.to_string().as_bytes());
+    }
+}
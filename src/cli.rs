@@ -0,0 +1,274 @@
+use simple_rust_cpu_raytracer::progress::StopConditions;
+use simple_rust_cpu_raytracer::scene::{Projection, Stereo};
+use simple_rust_cpu_raytracer::Pass;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const USAGE: &str = "\
+A simple, homebrewed, CPU-powered path tracer.
+
+Usage: simple_rust_cpu_raytracer [OPTIONS]
+
+Options:
+    -s, --scene <FILE>          Scene file to render [default: built-in demo scene]
+    -c, --camera <CAMERA>       Camera to render from, by name or index, or 'all' to render each
+                                camera to its own image with --headless [default: the first camera]
+        --projection <NAME>     Override the cameras' projection: perspective, orthographic, fisheye
+                                or equirectangular (a 360x180 panorama, best at a 2:1 image size)
+        --stereo <LAYOUT>       Render both eyes of the cameras: side_by_side, top_bottom or mono
+        --anamorphic <SQUEEZE>  Give the cameras an anamorphic lens, e.g. 2, saving images this many
+                                times wider than the render
+    -W, --width <PIXELS>        Image width [default: 400]
+    -H, --height <PIXELS>       Image height [default: 400]
+        --bounces <N>           Maximum number of bounces per path [default: 3]
+        --wormhole-bounces <N>  Maximum number of wormhole traversals per path [default: 10]
+        --chromatic-aberration <STRENGTH>
+                                Lateral and longitudinal chromatic aberration, e.g. 1 [default: 0]
+        --spectral              Trace wavelengths of light instead of red, green and blue
+        --spp <N>               Stop after N samples per pixel [headless default: 64]
+        --time <SECONDS>        Stop after this much wall-clock time
+        --noise <LEVEL>         Stop once the estimated per-pixel noise is under this level, e.g. 0.02
+        --checkpoint <FILE>     Save the render progress to this file every so often (headless)
+        --checkpoint-interval <SECONDS>
+                                Time between checkpoints [default: 60]
+        --resume <FILE>         Continue a headless render from a checkpoint, with its size, pass,
+                                seed, camera and, unless given, stop conditions
+        --seed <N>              Random seed [default: picked at random]
+    -o, --output <FILE>         Output image, .png .ppm .pfm or .hdr [default: render.png]
+        --pass <PASS>           Render pass: beauty, depth or normal [default: beauty]
+        --headless              Render without a window, write the output image and exit
+        --interactive           Open the viewer [default]
+        --animation             Render the scene's animation to a numbered image sequence, named after
+                                --output with '#' replaced by the frame number, e.g. frames/shot_####.png
+        --fps <N>               Frame rate of the image sequence [default: 24]
+        --frames <FIRST-LAST>   Only render these frames of the sequence, counting from 0
+        --coordinator <ADDRESS> Render headless with the help of workers connecting to this address,
+                                e.g. 0.0.0.0:7878
+        --worker <ADDRESS>      Render tiles for the coordinator at this address, e.g. 192.168.1.10:7878
+        --tile-size <PIXELS>    Size of the tiles handed to workers [default: 32]
+        --chunk-spp <N>         Samples per pixel handed to a worker at a time [default: all of them]
+        --replay-input <FILE>   Play back recorded viewer input before taking input from the window
+        --camera-path <FILE>    Fly the camera along a recorded path, in the viewer or with --animation
+        --record-path <FILE>    Where the viewer saves camera paths recorded with R [default: camera.path]
+    -h, --help                  Print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Interactive,
+    Headless,
+    Animation,
+    Coordinator,
+    Worker,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub mode: Mode,
+    pub scene: Option<PathBuf>,
+    // Name or index of the camera, or "all"
+    pub camera: Option<String>,
+    pub projection: Option<Projection>,
+    pub stereo: Option<Stereo>,
+    pub anamorphic_squeeze: Option<f32>,
+    pub width: usize,
+    pub height: usize,
+    pub max_bounces: i32,
+    pub max_wormhole_bounces: i32,
+    pub chromatic_aberration: f32,
+    pub spectral: bool,
+    pub stop: StopConditions,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume: Option<PathBuf>,
+    // Where the coordinator listens, or the worker connects to
+    pub address: String,
+    pub fps: f32,
+    pub frames: Option<(u32, u32)>,
+    pub tile_size: usize,
+    pub chunk_samples: Option<u32>,
+    pub seed: u64,
+    pub output: PathBuf,
+    pub pass: Pass,
+    pub replay_input: Option<PathBuf>,
+    pub camera_path: Option<PathBuf>,
+    pub record_path: PathBuf,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            mode: Mode::Interactive,
+            scene: None,
+            camera: None,
+            projection: None,
+            stereo: None,
+            anamorphic_squeeze: None,
+            width: 400,
+            height: 400,
+            max_bounces: 3,
+            max_wormhole_bounces: 10,
+            chromatic_aberration: 0.0,
+            spectral: false,
+            stop: StopConditions::default(),
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: None,
+            address: String::new(),
+            fps: 24.0,
+            frames: None,
+            tile_size: 32,
+            chunk_samples: None,
+            seed: random_seed(),
+            output: PathBuf::from("render.png"),
+            pass: Pass::Beauty,
+            replay_input: None,
+            camera_path: None,
+            record_path: PathBuf::from("camera.path"),
+            help: false,
+        }
+    }
+}
+
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// Parse command line arguments, not including the program name
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Accept both "--flag value" and "--flag=value"
+        let (flag, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match &flag[..] {
+            "-h" | "--help" => options.help = true,
+            "--headless" => options.mode = Mode::Headless,
+            "--interactive" => options.mode = Mode::Interactive,
+            "--animation" => options.mode = Mode::Animation,
+            "--fps" => options.fps = parse_positive_float(&flag, &value()?)?,
+            "--frames" => {
+                let range = value()?;
+                let (first, last) = match range.find('-') {
+                    Some(i) => (parse(&flag, &range[..i])?, parse(&flag, &range[i + 1..])?),
+                    None => {
+                        let frame = parse(&flag, &range)?;
+                        (frame, frame)
+                    }
+                };
+                if first > last {
+                    return Err(format!("{} range '{}' is empty", flag, range));
+                }
+                options.frames = Some((first, last));
+            }
+            "--coordinator" => {
+                options.mode = Mode::Coordinator;
+                options.address = value()?;
+            }
+            "--worker" => {
+                options.mode = Mode::Worker;
+                options.address = value()?;
+            }
+            "--tile-size" => options.tile_size = parse_positive(&flag, &value()?)?,
+            "--chunk-spp" => options.chunk_samples = Some(parse_positive(&flag, &value()?)?),
+            "-s" | "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "-c" | "--camera" => options.camera = Some(value()?),
+            "--projection" => {
+                let name = value()?;
+                options.projection = Some(
+                    Projection::from_name(&name).ok_or_else(|| format!("unknown projection '{}'", name))?,
+                );
+            }
+            "--stereo" => {
+                let name = value()?;
+                options.stereo =
+                    Some(Stereo::from_name(&name).ok_or_else(|| format!("unknown stereo layout '{}'", name))?);
+            }
+            "--anamorphic" => options.anamorphic_squeeze = Some(parse_positive_float(&flag, &value()?)?),
+            "-W" | "--width" => options.width = parse_positive(&flag, &value()?)?,
+            "-H" | "--height" => options.height = parse_positive(&flag, &value()?)?,
+            "--bounces" => options.max_bounces = parse(&flag, &value()?)?,
+            "--wormhole-bounces" => options.max_wormhole_bounces = parse(&flag, &value()?)?,
+            "--spectral" => options.spectral = true,
+            "--chromatic-aberration" => {
+                options.chromatic_aberration = parse(&flag, &value()?)?;
+                if options.chromatic_aberration < 0.0 || !options.chromatic_aberration.is_finite() {
+                    return Err(format!("{} can't be negative", flag));
+                }
+            }
+            "--spp" => options.stop.samples = Some(parse_positive(&flag, &value()?)?),
+            "--time" => options.stop.time_budget = Some(parse_seconds(&flag, &value()?)?),
+            "--noise" => options.stop.noise_threshold = Some(parse_positive_float(&flag, &value()?)?),
+            "--checkpoint" => options.checkpoint = Some(PathBuf::from(value()?)),
+            "--checkpoint-interval" => options.checkpoint_interval = parse_seconds(&flag, &value()?)?,
+            "--resume" => options.resume = Some(PathBuf::from(value()?)),
+            "--seed" => options.seed = parse(&flag, &value()?)?,
+            "-o" | "--output" => options.output = PathBuf::from(value()?),
+            "--pass" => {
+                options.pass = match &value()?[..] {
+                    "beauty" => Pass::Beauty,
+                    "depth" => Pass::Depth,
+                    "normal" => Pass::Normal,
+                    pass => return Err(format!("unknown render pass '{}'", pass)),
+                }
+            }
+            "--replay-input" => options.replay_input = Some(PathBuf::from(value()?)),
+            "--camera-path" => options.camera_path = Some(PathBuf::from(value()?)),
+            "--record-path" => options.record_path = PathBuf::from(value()?),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_seconds(flag: &str, value: &str) -> Result<Duration, String> {
+    let seconds: f32 = parse(flag, value)?;
+    if seconds <= 0.0 || !seconds.is_finite() {
+        return Err(format!("{} must be a positive number of seconds", flag));
+    }
+    Ok(Duration::from_secs_f32(seconds))
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(
+    flag: &str,
+    value: &str,
+) -> Result<T, String> {
+    let parsed: T = parse(flag, value)?;
+    if parsed > T::default() {
+        Ok(parsed)
+    } else {
+        Err(format!("{} must be larger than zero", flag))
+    }
+}
+
+// Like parse_positive, but floats also parse "inf", which no option can make sense of
+fn parse_positive_float(flag: &str, value: &str) -> Result<f32, String> {
+    let parsed: f32 = parse_positive(flag, value)?;
+    if parsed.is_finite() {
+        Ok(parsed)
+    } else {
+        Err(format!("invalid value '{}' for {}", value, flag))
+    }
+}
@@ -0,0 +1,73 @@
+// Brown-Conrady lens distortion, to match renders to footage from real lenses. Points are in
+// image heights from the image center, x to the right and y up, like projection_ray takes,
+// and the coefficients are for a radius of 1 at the top and bottom edges of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Distortion {
+    // Radial distortion, negative for barrel and positive for pincushion
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    // Tangential distortion, from a lens that isn't quite parallel to the sensor
+    pub p1: f32,
+    pub p2: f32,
+}
+
+impl Distortion {
+    pub fn is_none(&self) -> bool {
+        *self == Distortion::default()
+    }
+
+    // Where a point of the undistorted image ends up on the distorted one
+    pub fn distort(&self, x: f32, y: f32) -> (f32, f32) {
+        if self.is_none() {
+            return (x, y);
+        }
+        let (x, y) = (x * 2.0, y * 2.0);
+        let (radial, tangential_x, tangential_y) = self.terms(x, y);
+        ((x * radial + tangential_x) / 2.0, (y * radial + tangential_y) / 2.0)
+    }
+
+    // Where a point of the distorted image comes from, found with Newton's method. None for
+    // points that strong barrel distortion can't reach, outside the image circle it folds
+    // everything into.
+    pub fn undistort(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        if self.is_none() {
+            return Some((x, y));
+        }
+        let (target_x, target_y) = (x * 2.0, y * 2.0);
+        let (mut x, mut y) = (target_x, target_y);
+        for _ in 0..10 {
+            let (radial, tangential_x, tangential_y) = self.terms(x, y);
+            let (error_x, error_y) = (x * radial + tangential_x - target_x, y * radial + tangential_y - target_y);
+
+            // How the distorted point moves with the undistorted one
+            let r2 = x * x + y * y;
+            let slope = self.k1 + r2 * (2.0 * self.k2 + r2 * 3.0 * self.k3);
+            let xx = radial + 2.0 * x * x * slope + 2.0 * self.p1 * y + 6.0 * self.p2 * x;
+            let xy = 2.0 * x * y * slope + 2.0 * self.p1 * x + 2.0 * self.p2 * y;
+            let yy = radial + 2.0 * y * y * slope + 6.0 * self.p1 * y + 2.0 * self.p2 * x;
+            // Past the fold the image turns back on itself
+            let determinant = xx * yy - xy * xy;
+            if determinant <= 0.0 {
+                return None;
+            }
+            x -= (yy * error_x - xy * error_y) / determinant;
+            y -= (xx * error_y - xy * error_x) / determinant;
+        }
+
+        let (distorted_x, distorted_y) = self.distort(x / 2.0, y / 2.0);
+        if (distorted_x * 2.0 - target_x).abs() + (distorted_y * 2.0 - target_y).abs() > 1e-3 {
+            return None;
+        }
+        Some((x / 2.0, y / 2.0))
+    }
+
+    // The radial scale and the tangential offset at a point
+    fn terms(&self, x: f32, y: f32) -> (f32, f32, f32) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let tangential_x = 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+        let tangential_y = self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+        (radial, tangential_x, tangential_y)
+    }
+}
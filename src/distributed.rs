@@ -0,0 +1,649 @@
+use crate::animation::Animation;
+use crate::aperture::{Aperture, ApertureMask};
+use crate::distortion::Distortion;
+use crate::hdr::HdrImage;
+use crate::helpers::{Col, ObjectID};
+use crate::movement::Movement;
+use crate::render::{render_tile, Pass, RenderSettings, Tile};
+use crate::scene::{Camera, Material, Projection, Scene, Sky, Sphere, Stereo, WormholeParams};
+use cgmath::{Vector2, Vector3};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Rendering spread over several processes or machines, over plain TCP.
+//
+// The coordinator listens for workers. Every worker that connects gets the render
+// settings and the scene, then is handed one job at a time: a tile and a range of
+// samples. It sends back the summed radiance of the tile's pixels, which the
+// coordinator adds into the image. While rendering, the worker sends a heartbeat every
+// second. Jobs of a worker that disconnects, or goes quiet for longer than the worker
+// timeout, go back in the queue for the others.
+//
+// Everything is sent little-endian. Tiles rendered with all their samples in one job
+// give exactly the same image as a local render.
+
+// Changes with the format, so workers from another version are turned away
+const MAGIC: &[u8; 8] = b"RTDIST09";
+
+const DONE: u8 = 0;
+const JOB: u8 = 1;
+const HEARTBEAT: u8 = 2;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long the coordinator waits to hear from a worker before giving up on it
+pub const WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The most spheres, colors or pixels a setup may hold, enough for a 16k by 8k image or
+// environment map. Sizes come off the socket, so a broken coordinator can't ask a worker
+// for more memory than that.
+const MAX_ITEMS: usize = 1 << 27;
+
+// Some samples for every pixel of a tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Job {
+    pub tile: Tile,
+    pub first_sample: u32,
+    pub samples: u32,
+}
+
+impl Job {
+    // Cover `samples` samples per pixel with jobs of tile_size tiles and at most
+    // chunk samples each, in the order they should be handed out
+    pub fn split(width: usize, height: usize, tile_size: usize, samples: u32, chunk: u32) -> Vec<Job> {
+        let mut jobs = vec![];
+        for first_sample in (0..samples).step_by(chunk as usize) {
+            for tile in Tile::split(width, height, tile_size) {
+                jobs.push(Job {
+                    tile,
+                    first_sample,
+                    samples: chunk.min(samples - first_sample),
+                });
+            }
+        }
+        jobs
+    }
+}
+
+// What happened while coordinating, for progress output
+#[derive(Debug)]
+pub enum Event {
+    WorkerJoined(SocketAddr),
+    JobDone {
+        worker: SocketAddr,
+        done: usize,
+        total: usize,
+    },
+    WorkerLost(SocketAddr, io::Error),
+}
+
+struct Queue {
+    jobs: Vec<Job>,
+    in_flight: usize,
+    done: usize,
+    buffer: Vec<Col>,
+}
+
+// Hand the jobs out to workers connecting to the listener until they are all done,
+// and return the accumulated samples. The scene's active camera is the one rendered.
+pub fn coordinate(
+    listener: &TcpListener,
+    scene: &Scene,
+    settings: &RenderSettings,
+    pass: Pass,
+    jobs: Vec<Job>,
+    worker_timeout: Duration,
+    on_event: &(dyn Fn(Event) + Sync),
+) -> io::Result<Vec<Col>> {
+    let mut setup = vec![];
+    write_setup(&mut setup, scene, settings, pass)?;
+
+    let total = jobs.len();
+    let queue = Mutex::new(Queue {
+        // Popped from the back, so reverse to hand out the first job first
+        jobs: jobs.into_iter().rev().collect(),
+        in_flight: 0,
+        done: 0,
+        buffer: vec![Col::new(0.0, 0.0, 0.0); settings.width * settings.height],
+    });
+
+    listener.set_nonblocking(true)?;
+    thread::scope(|scope| -> io::Result<()> {
+        while queue.lock().unwrap().done < total {
+            match listener.accept() {
+                Ok((stream, worker)) => {
+                    on_event(Event::WorkerJoined(worker));
+                    let (queue, setup) = (&queue, &setup);
+                    scope.spawn(move || {
+                        let served = stream
+                            .set_read_timeout(Some(worker_timeout))
+                            .and_then(|_| stream.set_write_timeout(Some(worker_timeout)))
+                            .and_then(|_| serve_worker(stream, worker, setup, queue, settings.width, total, on_event));
+                        if let Err(e) = served {
+                            on_event(Event::WorkerLost(worker, e));
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(20)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    })?;
+
+    // Workers that connected while the last job came in are told there's nothing left,
+    // rather than having their connection reset
+    while let Ok((stream, _)) = listener.accept() {
+        let _ = send_done(stream, &setup);
+    }
+
+    Ok(queue.into_inner().unwrap().buffer)
+}
+
+fn send_done(stream: TcpStream, setup: &[u8]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(setup)?;
+    writer.write_all(&[DONE])?;
+    writer.flush()
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    worker: SocketAddr,
+    setup: &[u8],
+    queue: &Mutex<Queue>,
+    image_width: usize,
+    total: usize,
+    on_event: &(dyn Fn(Event) + Sync),
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(setup)?;
+
+    loop {
+        let job = match take_job(queue) {
+            Some(job) => job,
+            None => {
+                writer.write_all(&[DONE])?;
+                return writer.flush();
+            }
+        };
+
+        let result = write_job(&mut writer, &job)
+            .and_then(|_| writer.flush())
+            .and_then(|_| read_result(&mut reader, &job))
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    io::Error::new(io::ErrorKind::TimedOut, "the worker stopped responding")
+                }
+                _ => e,
+            });
+
+        let mut queue = queue.lock().unwrap();
+        queue.in_flight -= 1;
+        match result {
+            Ok(tile_buffer) => {
+                let tile = job.tile;
+                for (row, cols) in tile_buffer.chunks(tile.width).enumerate() {
+                    let start = (tile.y + row) * image_width + tile.x;
+                    for (pixel, col) in queue.buffer[start..start + tile.width].iter_mut().zip(cols) {
+                        *pixel += *col;
+                    }
+                }
+                queue.done += 1;
+                on_event(Event::JobDone {
+                    worker,
+                    done: queue.done,
+                    total,
+                });
+            }
+            Err(e) => {
+                queue.jobs.push(job);
+                return Err(e);
+            }
+        }
+    }
+}
+
+// The next job, or None once every job is done. While the last jobs are still being
+// worked on this waits, in case a worker drops out and its job comes back.
+fn take_job(queue: &Mutex<Queue>) -> Option<Job> {
+    loop {
+        {
+            let mut queue = queue.lock().unwrap();
+            if let Some(job) = queue.jobs.pop() {
+                queue.in_flight += 1;
+                return Some(job);
+            }
+            if queue.in_flight == 0 {
+                return None;
+            }
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// Connect to a coordinator, retrying until it's up or the timeout runs out
+pub fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if start.elapsed() >= timeout => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+// Render jobs for a coordinator until it says it's done. Returns the number of jobs done.
+pub fn work(stream: TcpStream) -> io::Result<usize> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (scene, settings, pass) = read_setup(&mut reader)?;
+    let movement = Movement::new(scene.camera());
+    let mut jobs_done = 0;
+
+    loop {
+        match read_u8(&mut reader)? {
+            DONE => return Ok(jobs_done),
+            JOB => {
+                let job = read_job(&mut reader)?;
+                let tile = job.tile;
+                if tile.x + tile.width > settings.width || tile.y + tile.height > settings.height {
+                    return Err(invalid_data("tile outside the image"));
+                }
+
+                let mut tile_buffer = vec![Col::new(0.0, 0.0, 0.0); tile.pixels()];
+                let (finished, rendered) = mpsc::channel();
+                thread::scope(|scope| -> io::Result<()> {
+                    let tile_buffer = &mut tile_buffer;
+                    let (scene, movement, settings) = (&scene, &movement, &settings);
+                    scope.spawn(move || {
+                        let samples = job.first_sample..job.first_sample + job.samples;
+                        render_tile(tile_buffer, tile, scene, pass, samples, movement, settings);
+                        let _ = finished.send(());
+                    });
+
+                    // Let the coordinator know this worker is still at it
+                    while let Err(RecvTimeoutError::Timeout) = rendered.recv_timeout(HEARTBEAT_INTERVAL) {
+                        writer.write_all(&[HEARTBEAT])?;
+                        writer.flush()?;
+                    }
+                    Ok(())
+                })?;
+
+                write_job(&mut writer, &job)?;
+                for col in &tile_buffer {
+                    write_col(&mut writer, *col)?;
+                }
+                writer.flush()?;
+                jobs_done += 1;
+            }
+            tag => return Err(invalid_data(&format!("unknown message {}", tag))),
+        }
+    }
+}
+
+fn read_result(reader: &mut impl Read, job: &Job) -> io::Result<Vec<Col>> {
+    let mut tag = read_u8(reader)?;
+    while tag == HEARTBEAT {
+        tag = read_u8(reader)?;
+    }
+    if tag != JOB || read_job(reader)? != *job {
+        return Err(invalid_data("the worker sent back a different job"));
+    }
+    (0..job.tile.pixels()).map(|_| read_col(reader)).collect()
+}
+
+fn write_setup(w: &mut impl Write, scene: &Scene, settings: &RenderSettings, pass: Pass) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    write_u32(w, settings.width as u32)?;
+    write_u32(w, settings.height as u32)?;
+    write_u32(w, settings.max_bounces as u32)?;
+    write_u32(w, settings.max_wormhole_bounces as u32)?;
+    write_f32(w, settings.chromatic_aberration_strength)?;
+    w.write_all(&[settings.spectral as u8])?;
+    w.write_all(&settings.seed.to_le_bytes())?;
+    w.write_all(&[match pass {
+        Pass::Beauty => 0,
+        Pass::Depth => 1,
+        Pass::Normal => 2,
+    }])?;
+
+    // Workers only need the camera being rendered
+    let camera = scene.camera();
+    write_vector(w, camera.pos)?;
+    write_vector(w, camera.rot)?;
+    write_f32(w, camera.fov)?;
+    write_f32(w, camera.focal_length)?;
+    write_f32(w, camera.aperture_radius)?;
+    write_aperture(w, &camera.aperture)?;
+    write_f32(w, camera.anamorphic_squeeze)?;
+    let distortion = &camera.distortion;
+    for value in [distortion.k1, distortion.k2, distortion.k3, distortion.p1, distortion.p2] {
+        write_f32(w, value)?;
+    }
+    write_f32(w, camera.vignetting)?;
+    write_f32(w, camera.optical_vignetting)?;
+    for value in [camera.shift.x, camera.shift.y, camera.tilt.x, camera.tilt.y] {
+        write_f32(w, value)?;
+    }
+    w.write_all(&[match camera.projection {
+        Projection::Perspective => 0,
+        Projection::Orthographic => 1,
+        Projection::Fisheye => 2,
+        Projection::Equirectangular => 3,
+    }])?;
+    write_f32(w, camera.ortho_height)?;
+    w.write_all(&[match camera.stereo {
+        Stereo::Mono => 0,
+        Stereo::SideBySide => 1,
+        Stereo::TopBottom => 2,
+    }])?;
+    write_f32(w, camera.interocular_distance)?;
+    write_f32(w, camera.convergence)?;
+    write_u32(w, i32::from(camera.object_id) as u32)?;
+
+    write_u32(w, scene.spheres.len() as u32)?;
+    for sphere in &scene.spheres {
+        write_vector(w, sphere.pos)?;
+        write_f32(w, sphere.radius)?;
+        write_material(w, &sphere.material)?;
+        write_u32(w, i32::from(sphere.object_id) as u32)?;
+    }
+
+    let sky = &scene.sky;
+    write_u32(w, sky.colors.len() as u32)?;
+    for col in &sky.colors {
+        write_col(w, *col)?;
+    }
+    write_f32(w, sky.intensity)?;
+    match &sky.environment {
+        Some(environment) => {
+            w.write_all(&[1])?;
+            write_u32(w, environment.width as u32)?;
+            write_u32(w, environment.height as u32)?;
+            for col in &environment.pixels {
+                write_col(w, *col)?;
+            }
+        }
+        None => w.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn read_setup(r: &mut impl Read) -> io::Result<(Scene, RenderSettings, Pass)> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a render coordinator, or one from another version"));
+    }
+
+    // Tiles must lie within the image, so this also bounds the size of every job
+    let (width, height) = read_size(r)?;
+    let settings = RenderSettings {
+        width,
+        height,
+        max_bounces: read_u32(r)? as i32,
+        max_wormhole_bounces: read_u32(r)? as i32,
+        chromatic_aberration_strength: read_f32(r)?,
+        spectral: read_u8(r)? != 0,
+        seed: {
+            let mut bytes = [0; 8];
+            r.read_exact(&mut bytes)?;
+            u64::from_le_bytes(bytes)
+        },
+    };
+    let pass = match read_u8(r)? {
+        0 => Pass::Beauty,
+        1 => Pass::Depth,
+        2 => Pass::Normal,
+        pass => return Err(invalid_data(&format!("unknown render pass {}", pass))),
+    };
+
+    let camera = Camera {
+        name: None,
+        pos: read_vector(r)?,
+        rot: read_vector(r)?,
+        fov: read_f32(r)?,
+        focal_length: read_f32(r)?,
+        aperture_radius: read_f32(r)?,
+        aperture: read_aperture(r)?,
+        anamorphic_squeeze: read_f32(r)?,
+        distortion: Distortion {
+            k1: read_f32(r)?,
+            k2: read_f32(r)?,
+            k3: read_f32(r)?,
+            p1: read_f32(r)?,
+            p2: read_f32(r)?,
+        },
+        vignetting: read_f32(r)?,
+        optical_vignetting: read_f32(r)?,
+        shift: Vector2::new(read_f32(r)?, read_f32(r)?),
+        tilt: Vector2::new(read_f32(r)?, read_f32(r)?),
+        projection: match read_u8(r)? {
+            0 => Projection::Perspective,
+            1 => Projection::Orthographic,
+            2 => Projection::Fisheye,
+            3 => Projection::Equirectangular,
+            projection => return Err(invalid_data(&format!("unknown projection {}", projection))),
+        },
+        ortho_height: read_f32(r)?,
+        stereo: match read_u8(r)? {
+            0 => Stereo::Mono,
+            1 => Stereo::SideBySide,
+            2 => Stereo::TopBottom,
+            stereo => return Err(invalid_data(&format!("unknown stereo layout {}", stereo))),
+        },
+        interocular_distance: read_f32(r)?,
+        convergence: read_f32(r)?,
+        object_id: ObjectID::from(read_u32(r)? as i32),
+    };
+
+    let sphere_count = read_count(r)?;
+    let mut spheres = vec![];
+    for _ in 0..sphere_count {
+        spheres.push(Sphere {
+            pos: read_vector(r)?,
+            radius: read_f32(r)?,
+            material: read_material(r)?,
+            object_id: ObjectID::from(read_u32(r)? as i32),
+        });
+    }
+
+    let color_count = read_count(r)?;
+    let colors = (0..color_count).map(|_| read_col(r)).collect::<io::Result<Vec<Col>>>()?;
+    let intensity = read_f32(r)?;
+    let environment = match read_u8(r)? {
+        0 => None,
+        _ => {
+            let (width, height) = read_size(r)?;
+            let pixels = (0..width * height).map(|_| read_col(r)).collect::<io::Result<Vec<Col>>>()?;
+            Some(Arc::new(HdrImage { width, height, pixels }))
+        }
+    };
+
+    let scene = Scene {
+        cameras: vec![camera],
+        active_camera: 0,
+        spheres,
+        sky: Sky {
+            colors,
+            intensity,
+            environment,
+        },
+        wireframes: vec![],
+        animation: Animation::default(),
+    };
+    Ok((scene, settings, pass))
+}
+
+fn write_material(w: &mut impl Write, material: &Material) -> io::Result<()> {
+    write_col(w, material.color)?;
+    write_f32(w, material.metallic)?;
+    write_f32(w, material.roughness)?;
+    write_col(w, material.emission_color)?;
+    write_f32(w, material.emission_intensity)?;
+    let wormhole = &material.wormhole_params;
+    w.write_all(&[wormhole.is_wormhole as u8])?;
+    write_vector(w, wormhole.wormhole_offset)?;
+    write_u32(w, i32::from(wormhole.other_end_object_id) as u32)
+}
+
+fn read_material(r: &mut impl Read) -> io::Result<Material> {
+    Ok(Material {
+        color: read_col(r)?,
+        metallic: read_f32(r)?,
+        roughness: read_f32(r)?,
+        emission_color: read_col(r)?,
+        emission_intensity: read_f32(r)?,
+        wormhole_params: WormholeParams {
+            is_wormhole: read_u8(r)? != 0,
+            wormhole_offset: read_vector(r)?,
+            other_end_object_id: ObjectID::from(read_u32(r)? as i32),
+        },
+    })
+}
+
+fn write_aperture(w: &mut impl Write, aperture: &Aperture) -> io::Result<()> {
+    match aperture {
+        Aperture::Circle => w.write_all(&[0]),
+        Aperture::Blades {
+            count,
+            rotation,
+            curvature,
+        } => {
+            w.write_all(&[1])?;
+            write_u32(w, *count)?;
+            write_f32(w, *rotation)?;
+            write_f32(w, *curvature)
+        }
+        Aperture::Mask(mask) => {
+            w.write_all(&[2])?;
+            write_u32(w, mask.width as u32)?;
+            write_u32(w, mask.height as u32)?;
+            for value in &mask.transmission {
+                write_f32(w, *value)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn read_aperture(r: &mut impl Read) -> io::Result<Aperture> {
+    match read_u8(r)? {
+        0 => Ok(Aperture::Circle),
+        1 => Ok(Aperture::Blades {
+            count: read_u32(r)?,
+            rotation: read_f32(r)?,
+            curvature: read_f32(r)?,
+        }),
+        2 => {
+            let (width, height) = read_size(r)?;
+            let transmission = (0..width * height).map(|_| read_f32(r)).collect::<io::Result<Vec<f32>>>()?;
+            let mask = ApertureMask::new(width, height, transmission).map_err(|e| invalid_data(&e))?;
+            Ok(Aperture::Mask(Arc::new(mask)))
+        }
+        aperture => Err(invalid_data(&format!("unknown aperture {}", aperture))),
+    }
+}
+
+fn write_job(w: &mut impl Write, job: &Job) -> io::Result<()> {
+    w.write_all(&[JOB])?;
+    write_u32(w, job.tile.x as u32)?;
+    write_u32(w, job.tile.y as u32)?;
+    write_u32(w, job.tile.width as u32)?;
+    write_u32(w, job.tile.height as u32)?;
+    write_u32(w, job.first_sample)?;
+    write_u32(w, job.samples)
+}
+
+// Reads a job, not including the message tag in front of it
+fn read_job(r: &mut impl Read) -> io::Result<Job> {
+    Ok(Job {
+        tile: Tile {
+            x: read_u32(r)? as usize,
+            y: read_u32(r)? as usize,
+            width: read_u32(r)? as usize,
+            height: read_u32(r)? as usize,
+        },
+        first_sample: read_u32(r)?,
+        samples: read_u32(r)?,
+    })
+}
+
+fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_f32(w: &mut impl Write, value: f32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_vector(w: &mut impl Write, v: Vector3<f32>) -> io::Result<()> {
+    write_f32(w, v.x)?;
+    write_f32(w, v.y)?;
+    write_f32(w, v.z)
+}
+
+fn write_col(w: &mut impl Write, col: Col) -> io::Result<()> {
+    write_f32(w, col.r)?;
+    write_f32(w, col.g)?;
+    write_f32(w, col.b)
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(r: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+// A number of items to read, which must be no more than MAX_ITEMS
+fn read_count(r: &mut impl Read) -> io::Result<usize> {
+    let count = read_u32(r)? as usize;
+    if count > MAX_ITEMS {
+        return Err(invalid_data(&format!("{} items is too many", count)));
+    }
+    Ok(count)
+}
+
+// The width and height of an image, with no more than MAX_ITEMS pixels
+fn read_size(r: &mut impl Read) -> io::Result<(usize, usize)> {
+    let width = read_u32(r)? as usize;
+    let height = read_u32(r)? as usize;
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_ITEMS => Ok((width, height)),
+        _ => Err(invalid_data(&format!("a {}x{} image is too large", width, height))),
+    }
+}
+
+fn read_vector(r: &mut impl Read) -> io::Result<Vector3<f32>> {
+    Ok(Vector3::new(read_f32(r)?, read_f32(r)?, read_f32(r)?))
+}
+
+fn read_col(r: &mut impl Read) -> io::Result<Col> {
+    Ok(Col::new(read_f32(r)?, read_f32(r)?, read_f32(r)?))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,58 @@
+use crate::input::{InputSource, Key};
+use minifb::{MouseMode, Window};
+
+// Input straight from the viewer window
+impl InputSource for Window {
+    fn keys(&self) -> Vec<Key> {
+        self.get_keys()
+            .map(|keys| keys.iter().filter_map(|key| from_minifb(*key)).collect())
+            .unwrap_or_default()
+    }
+
+    fn mouse_pos(&self) -> Option<(f32, f32)> {
+        self.get_unscaled_mouse_pos(MouseMode::Pass)
+    }
+}
+
+fn from_minifb(key: minifb::Key) -> Option<Key> {
+    use minifb::Key as K;
+
+    Some(match key {
+        K::A => Key::A,
+        K::B => Key::B,
+        K::C => Key::C,
+        K::D => Key::D,
+        K::E => Key::E,
+        K::F => Key::F,
+        K::G => Key::G,
+        K::H => Key::H,
+        K::I => Key::I,
+        K::J => Key::J,
+        K::K => Key::K,
+        K::L => Key::L,
+        K::M => Key::M,
+        K::N => Key::N,
+        K::O => Key::O,
+        K::P => Key::P,
+        K::Q => Key::Q,
+        K::R => Key::R,
+        K::S => Key::S,
+        K::T => Key::T,
+        K::U => Key::U,
+        K::V => Key::V,
+        K::W => Key::W,
+        K::X => Key::X,
+        K::Y => Key::Y,
+        K::Z => Key::Z,
+        K::Space => Key::Space,
+        K::LeftShift => Key::LeftShift,
+        K::Left => Key::Left,
+        K::Right => Key::Right,
+        K::Up => Key::Up,
+        K::Down => Key::Down,
+        K::Enter => Key::Enter,
+        K::Backspace => Key::Backspace,
+        K::Escape => Key::Escape,
+        _ => return None,
+    })
+}
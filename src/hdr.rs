@@ -0,0 +1,260 @@
+use crate::helpers::{clamp, Col};
+use cgmath::Vector3;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Radiance HDR (RGBE) images. Pixels are stored in display order, top row first,
+// which matches the standard "-Y <height> +X <width>" resolution string.
+
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Col>,
+}
+
+impl HdrImage {
+    // Look up a direction in a latitude-longitude (equirectangular) environment image, z up
+    pub fn sample_equirectangular(&self, dir: Vector3<f32>) -> Col {
+        let u = dir.y.atan2(dir.x) / (2.0 * std::f32::consts::PI) + 0.5;
+        let v = clamp(dir.z, -1.0, 1.0).acos() / std::f32::consts::PI;
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+
+        self.pixels[y * self.width + x]
+    }
+}
+
+// Shared exponent encoding: the largest component sets the exponent
+pub fn col_to_rgbe(col: Col) -> [u8; 4] {
+    let v = col.r.max(col.g).max(col.b);
+    if v.is_nan() || v <= 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    let channel = |c: f32| (c.max(0.0) * scale).min(255.0) as u8;
+
+    [
+        channel(col.r),
+        channel(col.g),
+        channel(col.b),
+        (exponent + 128) as u8,
+    ]
+}
+
+pub fn rgbe_to_col(rgbe: [u8; 4]) -> Col {
+    if rgbe[3] == 0 {
+        return Col::new(0.0, 0.0, 0.0);
+    }
+
+    let f = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    Col::new(rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f)
+}
+
+pub fn write_hdr(path: &Path, buffer: &[Col], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(
+        file,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut channels = vec![vec![0u8; width]; 4];
+    for row in buffer.chunks(width) {
+        let scanline: Vec<[u8; 4]> = row.iter().map(|col| col_to_rgbe(*col)).collect();
+
+        // The RLE scheme can only describe scanlines of 8 to 32767 pixels
+        if !(8..=0x7fff).contains(&width) {
+            for rgbe in &scanline {
+                file.write_all(rgbe)?;
+            }
+            continue;
+        }
+
+        file.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for (x, rgbe) in scanline.iter().enumerate() {
+            for c in 0..4 {
+                channels[c][x] = rgbe[c];
+            }
+        }
+        for channel in &channels {
+            write_rle_channel(&mut file, channel)?;
+        }
+    }
+
+    file.flush()
+}
+
+// Runs of four or more equal bytes are stored as (128 + count, value),
+// everything else as (count, bytes...)
+fn write_rle_channel(file: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+
+    let mut cur = 0;
+    while cur < data.len() {
+        // Find the next run long enough to be worth encoding
+        let mut run_start = cur;
+        let mut run_count = 0;
+        while run_start < data.len() {
+            run_count = 1;
+            while run_start + run_count < data.len()
+                && run_count < 127
+                && data[run_start + run_count] == data[run_start]
+            {
+                run_count += 1;
+            }
+            if run_count >= MIN_RUN {
+                break;
+            }
+            run_start += run_count;
+        }
+
+        // A short run just before the long one is cheaper as a run of its own
+        if run_start - cur > 1 && run_start - cur < MIN_RUN {
+            let count = run_start - cur;
+            if data[cur..run_start].iter().all(|b| *b == data[cur]) {
+                file.write_all(&[128 + count as u8, data[cur]])?;
+                cur = run_start;
+            }
+        }
+
+        // Literal bytes up to the run
+        while cur < run_start {
+            let count = (run_start - cur).min(128);
+            file.write_all(&[count as u8])?;
+            file.write_all(&data[cur..cur + count])?;
+            cur += count;
+        }
+
+        if run_count >= MIN_RUN {
+            file.write_all(&[128 + run_count as u8, data[run_start]])?;
+            cur += run_count;
+        }
+    }
+
+    Ok(())
+}
+
+// The most pixels read_hdr takes, enough for a 16k by 8k environment map. The size comes
+// from the file, so a broken one can't ask for more memory than that.
+const MAX_PIXELS: usize = 1 << 27;
+
+pub fn read_hdr(path: &Path) -> io::Result<HdrImage> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut line = String::new();
+    file.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+
+    // Header lines until the first empty line
+    loop {
+        line.clear();
+        if file.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of header"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("unsupported pixel format"));
+        }
+    }
+
+    line.clear();
+    file.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (width, height): (usize, usize) = match resolution[..] {
+        ["-Y", height, "+X", width] => (
+            width.parse().map_err(|_| invalid_data("bad image width"))?,
+            height
+                .parse()
+                .map_err(|_| invalid_data("bad image height"))?,
+        ),
+        _ => return Err(invalid_data("unsupported image orientation")),
+    };
+
+    match width.checked_mul(height) {
+        Some(pixels) if width <= MAX_PIXELS && pixels <= MAX_PIXELS => (),
+        _ => return Err(invalid_data("image too large")),
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(&mut file, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|rgbe| rgbe_to_col(*rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn read_scanline(file: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if width == 0 {
+        return Ok(());
+    }
+
+    let mut rgbe = [0u8; 4];
+    file.read_exact(&mut rgbe)?;
+
+    // Flat scanline
+    if !(8..=0x7fff).contains(&width) || rgbe[0] != 2 || rgbe[1] != 2 || rgbe[2] & 0x80 != 0 {
+        scanline[0] = rgbe;
+        for pixel in scanline[1..].iter_mut() {
+            file.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    if ((rgbe[2] as usize) << 8 | rgbe[3] as usize) != width {
+        return Err(invalid_data("wrong scanline width"));
+    }
+
+    // Run length encoded scanline, one channel at a time
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut byte = [0u8; 1];
+            file.read_exact(&mut byte)?;
+            let count = byte[0] as usize;
+
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid_data("bad scanline run"));
+                }
+                file.read_exact(&mut byte)?;
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[c] = byte[0];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad scanline data"));
+                }
+                for pixel in scanline[x..x + count].iter_mut() {
+                    file.read_exact(&mut byte)?;
+                    pixel[c] = byte[0];
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -346,6 +346,12 @@ impl From<i32> for ObjectID {
     }
 }
 
+impl From<ObjectID> for i32 {
+    fn from(id: ObjectID) -> i32 {
+        id.val
+    }
+}
+
 impl PartialEq for ObjectID {
     fn eq(&self, other: &Self) -> bool {
         self.val == other.val
@@ -366,9 +372,9 @@ pub struct Axis {
     dimensions: usize,
 }
 
-impl Into<usize> for Axis {
-    fn into(self) -> usize {
-        self.axis
+impl From<Axis> for usize {
+    fn from(axis: Axis) -> usize {
+        axis.axis
     }
 }
 
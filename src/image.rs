@@ -0,0 +1,257 @@
+use crate::hdr::write_hdr;
+use crate::helpers::{clamp, clamp_max, col_to_rgb_u32, Col};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// All buffers handled here are in display order: index = y * width + x, with the
+// top row first. This is the order the frame buffer is shown in, and it already
+// accounts for the flipped uv/index math in camera_ray.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Pfm,
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match &extension[..] {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+// Average the accumulated samples into radiance
+pub fn average(render_buffer: &[Col], sample_iter: u32) -> Vec<Col> {
+    let samples = sample_iter.max(1) as f32;
+    render_buffer.iter().map(|col| *col / samples).collect()
+}
+
+// Stretch an image squeezed by an anamorphic lens back out to its real width, blending
+// neighbouring columns. Returns the image and its new width.
+pub fn desqueeze(image: &[Col], width: usize, height: usize, squeeze: f32) -> (Vec<Col>, usize) {
+    if squeeze == 1.0 {
+        return (image.to_vec(), width);
+    }
+    let new_width = ((width as f32 * squeeze).round() as usize).max(1);
+    let scale = width as f32 / new_width as f32;
+
+    let mut stretched = Vec::with_capacity(new_width * height);
+    for row in image.chunks_exact(width) {
+        for x in 0..new_width {
+            let source = clamp((x as f32 + 0.5) * scale - 0.5, 0.0, (width - 1) as f32);
+            let left = source.floor() as usize;
+            let right = (left + 1).min(width - 1);
+            let t = source - left as f32;
+            stretched.push(row[left] * (1.0 - t) + row[right] * t);
+        }
+    }
+    (stretched, new_width)
+}
+
+// Clamp radiance to [0, 1] and pack it into 0RGB pixels
+pub fn tonemap(radiance: &[Col]) -> Vec<u32> {
+    radiance
+        .iter()
+        .map(|col| {
+            col_to_rgb_u32(Col::new(
+                clamp_max(col.r, 1.0),
+                clamp_max(col.g, 1.0),
+                clamp_max(col.b, 1.0),
+            ))
+        })
+        .collect()
+}
+
+// Save the accumulation buffer, picking the format from the file extension. Images from
+// an anamorphic camera are saved de-squeezed, wider than they were rendered.
+pub fn save_image(
+    path: &Path,
+    render_buffer: &[Col],
+    sample_iter: u32,
+    width: usize,
+    height: usize,
+    squeeze: f32,
+) -> io::Result<()> {
+    let (radiance, width) = desqueeze(&average(render_buffer, sample_iter), width, height, squeeze);
+
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => write_png(path, &tonemap(&radiance), width, height),
+        Some(ImageFormat::Ppm) => write_ppm(path, &tonemap(&radiance), width, height),
+        Some(ImageFormat::Pfm) => write_pfm(path, &radiance, width, height),
+        Some(ImageFormat::Hdr) => write_hdr(path, &radiance, width, height),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image format: {}", path.display()),
+        )),
+    }
+}
+
+// Write a binary (P6) PPM from a 0RGB frame buffer
+pub fn write_ppm(path: &Path, buffer: &[u32], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", width, height)?;
+
+    for pixel in buffer {
+        file.write_all(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])?;
+    }
+
+    file.flush()
+}
+
+// Read a binary (P5) grayscale PGM, with values from 0 to 1
+pub fn read_pgm(path: &Path) -> io::Result<(usize, usize, Vec<f32>)> {
+    let data = fs::read(path)?;
+    let invalid_data = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // Four header fields separated by whitespace and comments, then a single whitespace byte
+    let mut fields = vec![];
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match data.get(pos) {
+            Some(b'#') => {
+                while data.get(pos).is_some_and(|&byte| byte != b'\n') {
+                    pos += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while data.get(pos).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+            }
+            None => return Err(invalid_data("unexpected end of header")),
+        }
+    }
+    if fields[0] != "P5" {
+        return Err(invalid_data("not a binary grayscale PGM"));
+    }
+    let number = |field: &str| field.parse::<usize>().map_err(|_| invalid_data("bad image header"));
+    let (width, height, max) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+    if max == 0 || max > 65535 {
+        return Err(invalid_data("bad maximum value"));
+    }
+
+    // Values over 255 take two bytes, most significant first
+    let bytes = if max > 255 { 2 } else { 1 };
+    let pixels = &data[(pos + 1).min(data.len())..];
+    if pixels.len() < width * height * bytes {
+        return Err(invalid_data("unexpected end of image data"));
+    }
+    let values = pixels
+        .chunks(bytes)
+        .take(width * height)
+        .map(|value| value.iter().fold(0, |sum, &byte| sum * 256 + byte as usize) as f32 / max as f32)
+        .collect();
+    Ok((width, height, values))
+}
+
+// Write a little-endian colour PFM. PFM stores its rows bottom to top.
+pub fn write_pfm(path: &Path, buffer: &[Col], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+
+    for row in buffer.chunks(width).rev() {
+        for col in row {
+            file.write_all(&col.r.to_le_bytes())?;
+            file.write_all(&col.g.to_le_bytes())?;
+            file.write_all(&col.b.to_le_bytes())?;
+        }
+    }
+
+    file.flush()
+}
+
+// Write an 8-bit RGB PNG from a 0RGB frame buffer.
+// The image data is stored in uncompressed deflate blocks, which keeps the encoder tiny.
+pub fn write_png(path: &Path, buffer: &[u32], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, colour type RGB, default compression, filter and interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_png_chunk(&mut file, b"IHDR", &header)?;
+
+    // Every scanline is prefixed with filter type 0 (none)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in buffer.chunks(width) {
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+    }
+    write_png_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+    write_png_chunk(&mut file, b"IEND", &[])?;
+
+    file.flush()
+}
+
+fn write_png_chunk(file: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.write_all(&(data.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(data)?;
+
+    let mut crc = crc32_update(0xffff_ffff, kind);
+    crc = crc32_update(crc, data);
+    file.write_all(&(crc ^ 0xffff_ffff).to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 65535;
+
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    // Deflate, 32K window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = if blocks.peek().is_none() { 1 } else { 0 };
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
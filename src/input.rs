@@ -0,0 +1,331 @@
+use crate::app::Viewport;
+use crate::helpers::{clamp, clamp_min, Col};
+use crate::movement::Movement;
+use crate::scene::{Scene, MAX_TILT};
+use cgmath::Vector3;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Keys the viewer responds to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    A, B, C, D, E, F, G, H, I, J, K, L, M,
+    N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Space,
+    LeftShift,
+    Left,
+    Right,
+    Up,
+    Down,
+    Enter,
+    Backspace,
+    Escape,
+}
+
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E),
+    ("F", Key::F), ("G", Key::G), ("H", Key::H), ("I", Key::I), ("J", Key::J),
+    ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N), ("O", Key::O),
+    ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T),
+    ("U", Key::U), ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("Space", Key::Space),
+    ("LeftShift", Key::LeftShift),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Enter", Key::Enter),
+    ("Backspace", Key::Backspace),
+    ("Escape", Key::Escape),
+];
+
+impl Key {
+    pub fn from_name(name: &str) -> Option<Key> {
+        KEY_NAMES.iter().find(|(n, _)| *n == name).map(|(_, key)| *key)
+    }
+
+    pub fn name(&self) -> &'static str {
+        KEY_NAMES.iter().find(|(_, key)| key == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+// Where the viewer gets its input from: a window, a replay file or a test
+pub trait InputSource {
+    // Keys currently held down
+    fn keys(&self) -> Vec<Key>;
+    // Mouse position in window pixels, if known
+    fn mouse_pos(&self) -> Option<(f32, f32)>;
+}
+
+// Mouse position and held keys for one frame
+type ReplayFrame = (Option<(f32, f32)>, Vec<Key>);
+
+// Input played back from a file, one frame per line:
+//
+//     # mouse_x mouse_y keys...
+//     200 200 W
+//     210 200 W LeftShift
+//     - - Enter
+//
+// A mouse position of "- -" means the mouse is outside the window.
+#[derive(Debug, Clone)]
+pub struct ReplayInput {
+    frames: Vec<ReplayFrame>,
+    frame: usize,
+}
+
+impl ReplayInput {
+    pub fn parse(source: &str) -> Result<ReplayInput, String> {
+        let mut frames = vec![];
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 {
+                return Err(error("expected a mouse position".into()));
+            }
+            let mouse = match (fields[0], fields[1]) {
+                ("-", "-") => None,
+                (x, y) => match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => Some((x, y)),
+                    _ => return Err(error(format!("invalid mouse position '{} {}'", x, y))),
+                },
+            };
+            let keys = fields[2..]
+                .iter()
+                .map(|name| Key::from_name(name).ok_or_else(|| error(format!("unknown key '{}'", name))))
+                .collect::<Result<Vec<Key>, String>>()?;
+
+            frames.push((mouse, keys));
+        }
+
+        Ok(ReplayInput { frames, frame: 0 })
+    }
+
+    pub fn load(path: &Path) -> io::Result<ReplayInput> {
+        let source = fs::read_to_string(path)?;
+        ReplayInput::parse(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Move on to the next frame. Returns false once every frame has been played.
+    pub fn advance(&mut self) -> bool {
+        if self.frame < self.frames.len() {
+            self.frame += 1;
+        }
+        !self.finished()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.frames.len()
+    }
+}
+
+impl InputSource for ReplayInput {
+    fn keys(&self) -> Vec<Key> {
+        self.frames.get(self.frame).map_or(vec![], |(_, keys)| keys.clone())
+    }
+
+    fn mouse_pos(&self) -> Option<(f32, f32)> {
+        self.frames.get(self.frame).and_then(|(mouse, _)| *mouse)
+    }
+}
+
+pub fn handle_input(
+    input: &impl InputSource,
+    viewport: &mut Viewport,
+    scene: &mut Scene,
+    render_buffer: &mut Vec<Col>,
+    movement: &mut Movement,
+    keys_down: &mut Vec<Key>,
+    (display_width, display_height): (usize, usize),
+) {
+    const MOVE_SPEED: f32 = 0.2;
+    const ROT_SPEED: f32 = 0.1;
+    const MOUSE_SENSITIVITY: f32 = 100.0;
+    const SHIFT_SPEED: f32 = 0.01;
+    const TILT_SPEED: f32 = 1.0;
+
+    for key in input.keys() {
+        // Cycle through the cameras, or add one where the current camera is
+        if matches!(key, Key::C | Key::V) && !keys_down.contains(&key) {
+            if key == Key::C {
+                scene.active_camera = (scene.active_camera + 1) % scene.cameras.len();
+            } else {
+                scene.add_camera();
+            }
+            movement.camera_rotation = Movement::new(scene.camera()).camera_rotation;
+            *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+            viewport.sample_iter = 0;
+        }
+
+        let camera = scene.camera_mut();
+        match key {
+            Key::W => movement.camera_movement.y += MOVE_SPEED,
+            Key::S => movement.camera_movement.y -= MOVE_SPEED,
+            Key::A => movement.camera_movement.x -= MOVE_SPEED,
+            Key::D => movement.camera_movement.x += MOVE_SPEED,
+            Key::Space => movement.camera_movement.z += MOVE_SPEED,
+            Key::LeftShift => movement.camera_movement.z -= MOVE_SPEED,
+            Key::Left => camera.rot.z += ROT_SPEED,
+            Key::Right => camera.rot.z -= ROT_SPEED,
+            Key::Up => camera.rot.x += ROT_SPEED,
+            Key::Down => camera.rot.x -= ROT_SPEED,
+            Key::J => camera.focal_length *= 0.9,
+            Key::L => camera.focal_length /= 0.9,
+            Key::I => camera.aperture_radius += 0.01,
+            Key::M => camera.aperture_radius -= 0.01,
+            Key::Z => camera.zoom(0.95),
+            Key::X => camera.zoom(1.0 / 0.95),
+            Key::O => viewport.chromatic_aberration_strength -= 0.05,
+            Key::P => viewport.chromatic_aberration_strength += 0.05,
+            Key::Y => camera.shift.y += SHIFT_SPEED,
+            Key::B => camera.shift.y -= SHIFT_SPEED,
+            Key::E => camera.shift.x += SHIFT_SPEED,
+            Key::Q => camera.shift.x -= SHIFT_SPEED,
+            Key::T => camera.tilt.x += TILT_SPEED,
+            Key::G => camera.tilt.x -= TILT_SPEED,
+            Key::H => camera.tilt.y += TILT_SPEED,
+            Key::F => camera.tilt.y -= TILT_SPEED,
+            _ => (),
+        };
+        match key {
+            Key::Left | Key::Right | Key::Up | Key::Down => {
+                movement.camera_rotation =
+                    cgmath::Matrix4::from_angle_z(cgmath::Rad(camera.rot.z))
+                        * cgmath::Matrix4::from_angle_y(cgmath::Rad(camera.rot.y))
+                        * cgmath::Matrix4::from_angle_x(cgmath::Rad(camera.rot.x));
+            }
+            _ => (),
+        };
+        match key {
+            Key::W
+            | Key::S
+            | Key::A
+            | Key::D
+            | Key::Space
+            | Key::LeftShift
+            | Key::Left
+            | Key::Right
+            | Key::Up
+            | Key::Down
+            | Key::J
+            | Key::L
+            | Key::I
+            | Key::M
+            | Key::Z
+            | Key::X
+            | Key::O
+            | Key::P
+            | Key::Y
+            | Key::B
+            | Key::E
+            | Key::Q
+            | Key::T
+            | Key::G
+            | Key::H
+            | Key::F => {
+                *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+                viewport.sample_iter = 0;
+
+                let pos = (movement.camera_rotation * movement.camera_movement.extend(0.0))
+                    .truncate();
+                camera.pos += pos;
+                camera.focal_length = clamp_min(camera.focal_length, 0.0);
+                camera.aperture_radius = clamp_min(camera.aperture_radius, 0.0);
+                camera.fov = clamp(camera.fov, f32::MIN_POSITIVE, camera.projection.max_fov());
+                viewport.chromatic_aberration_strength = clamp_min(viewport.chromatic_aberration_strength, 0.0);
+                camera.tilt.x = clamp(camera.tilt.x, -MAX_TILT, MAX_TILT);
+                camera.tilt.y = clamp(camera.tilt.y, -MAX_TILT, MAX_TILT);
+            }
+
+            // Toggle overlays
+            Key::U if !keys_down.contains(&key) => {
+                viewport.overlays_enabled = !viewport.overlays_enabled;
+            }
+
+            // Toggle between overlays lined up with the lens distortion and undistorted ones
+            Key::K if !keys_down.contains(&key) => {
+                viewport.undistorted_overlays = !viewport.undistorted_overlays;
+            }
+
+            // Start or stop recording the camera path
+            Key::R if !keys_down.contains(&key) => {
+                viewport.recording = !viewport.recording;
+            }
+
+            // Toggle autofocus
+            Key::N if !keys_down.contains(&key) => {
+                viewport.autofocus = !viewport.autofocus;
+                *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+                viewport.sample_iter = 0;
+            }
+
+            // Toggle depth pass
+            Key::Enter if !keys_down.contains(&key) => {
+                viewport.depth_pass = !viewport.depth_pass;
+                *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+                viewport.sample_iter = 0;
+            }
+
+            // Toggle normal pass
+            Key::Backspace if !keys_down.contains(&key) => {
+                viewport.normal_pass = !viewport.normal_pass;
+                *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+                viewport.sample_iter = 0;
+            }
+            _ => (),
+        };
+    }
+
+    // Reset keys_down
+    *keys_down = vec![];
+    input.keys().iter().for_each(|key| keys_down.push(*key));
+
+    // Mouse movement
+    let camera = scene.camera_mut();
+    if let Some(mouse) = input.mouse_pos() {
+        if movement.mouse_movement
+            != Vector3::new(mouse.0 / MOUSE_SENSITIVITY, mouse.1 / MOUSE_SENSITIVITY, 0.0)
+        {
+            let mouse_delta = Vector3::new(
+                movement.mouse_movement.y - mouse.1 / MOUSE_SENSITIVITY,
+                0.0,
+                movement.mouse_movement.x - mouse.0 / MOUSE_SENSITIVITY,
+            );
+
+            camera.rot += mouse_delta * camera.fov / 90.0;
+
+            // Constrain vertical rotation.
+            camera.rot.x = clamp(
+                camera.rot.x,
+                std::f32::consts::PI / -2.0,
+                std::f32::consts::PI / 2.0,
+            );
+
+            movement.mouse_movement.x = mouse.0 / MOUSE_SENSITIVITY;
+            movement.mouse_movement.y = mouse.1 / MOUSE_SENSITIVITY;
+
+            movement.camera_rotation = cgmath::Matrix4::from_angle_z(cgmath::Rad(camera.rot.z))
+                * cgmath::Matrix4::from_angle_y(cgmath::Rad(camera.rot.y))
+                * cgmath::Matrix4::from_angle_x(cgmath::Rad(camera.rot.x));
+
+            *render_buffer = vec![Col::new(0.0, 0.0, 0.0); display_width * display_height];
+            viewport.sample_iter = 0;
+
+            movement.moving = true;
+        } else {
+            movement.moving = false;
+        }
+    }
+
+    movement.moving = movement.camera_movement != Vector3::new(0.0, 0.0, 0.0);
+
+    movement.camera_movement = Vector3::new(0.0, 0.0, 0.0);
+}
@@ -0,0 +1,35 @@
+// A simple, homebrewed, CPU-powered path tracer.
+//
+// The library holds everything needed to load a scene and render it into a buffer.
+// The viewer binary (src/main.rs) adds a window on top. The window is behind the
+// "gui" feature, while the input handling works on any InputSource.
+
+pub mod animation;
+pub mod aperture;
+pub mod app;
+pub mod bresenham;
+pub mod camera_path;
+pub mod checkpoint;
+pub mod distortion;
+pub mod distributed;
+pub mod hdr;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod helpers;
+pub mod input;
+pub mod image;
+pub mod intersect;
+pub mod movement;
+pub mod pathtrace;
+pub mod progress;
+pub mod render;
+pub mod rng;
+pub mod scene;
+pub mod scene_file;
+pub mod skybox;
+pub mod spectrum;
+pub mod watch;
+
+pub use crate::helpers::Col;
+pub use crate::render::{Pass, RenderSettings, Renderer};
+pub use crate::scene::{Camera, Scene};
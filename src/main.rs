@@ -1,158 +1,660 @@
-use crate::app::*;
-use crate::helpers::*;
-use crate::movement::*;
-use crate::pathtrace::*;
-use crate::scene::*;
-
-mod app;
-mod bresenham;
-mod helpers;
-mod intersect;
-mod movement;
-mod pathtrace;
-mod scene;
-mod skybox;
-
-use cgmath::Vector3;
-use minifb::{Key, Window, WindowOptions};
-use rand::thread_rng;
-use rayon::prelude::*;
-use std::time::{Instant, Duration};
+use crate::cli::{parse_args, Mode, Options, USAGE};
+use crate::interrupt::{catch_interrupt, interrupted};
+use simple_rust_cpu_raytracer::animation::frame_path;
+use simple_rust_cpu_raytracer::camera_path::CameraPath;
+use simple_rust_cpu_raytracer::app::*;
+use simple_rust_cpu_raytracer::checkpoint::{scene_hash, Checkpoint};
+use simple_rust_cpu_raytracer::distributed::{self, Event, Job};
+use simple_rust_cpu_raytracer::helpers::*;
+use simple_rust_cpu_raytracer::image::save_image;
+use simple_rust_cpu_raytracer::progress::{format_duration, Progress, StopConditions};
+use simple_rust_cpu_raytracer::render::*;
+use simple_rust_cpu_raytracer::scene::*;
+use simple_rust_cpu_raytracer::scene_file::load_scene;
 
-const WIDTH: usize = 400;
-const HEIGHT: usize = 400;
+mod cli;
+mod interrupt;
 
-const CHROMATIC_ABERRATION_STRENGTH: f32 = 0.0;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, Duration};
 
 fn main() {
-    let mut output_buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-    let mut render_buffer: Vec<Col> = vec![Col::new(0.0, 0.0, 0.0); WIDTH * HEIGHT];
-    let mut window = Window::new("", WIDTH, HEIGHT, WindowOptions::default()).unwrap_or_else(|e| {
-        panic!("{}", e);
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}\nRun with --help to see the available options.", e);
+        std::process::exit(2);
     });
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+
+    if options.mode != Mode::Headless && (options.resume.is_some() || options.checkpoint.is_some()) {
+        eprintln!("error: --resume and --checkpoint only work with --headless");
+        std::process::exit(2);
+    }
 
-    let mut scene = initialize_scene();
+    let all_cameras = options.camera.as_deref() == Some("all");
+    if all_cameras && (options.mode != Mode::Headless || options.checkpoint.is_some() || options.resume.is_some()) {
+        eprintln!("error: --camera all only works with --headless, without --checkpoint or --resume");
+        std::process::exit(2);
+    }
 
-    let mut viewport = Viewport {
+    if options.camera_path.is_some() && !matches!(options.mode, Mode::Interactive | Mode::Animation) {
+        eprintln!("error: --camera-path only works in the viewer or with --animation");
+        std::process::exit(2);
+    }
+
+    // Workers get everything they need from the coordinator
+    if options.mode == Mode::Worker {
+        run_worker(&options.address);
+        return;
+    }
+
+    // A resumed render takes its settings from the checkpoint
+    let mut resume = options.resume.as_ref().map(|path| {
+        Checkpoint::load(path).unwrap_or_else(|e| {
+            eprintln!("error: could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+    let settings = match &resume {
+        Some(checkpoint) => checkpoint.settings.clone(),
+        None => RenderSettings {
+            width: options.width,
+            height: options.height,
+            max_bounces: options.max_bounces,
+            max_wormhole_bounces: options.max_wormhole_bounces,
+            chromatic_aberration_strength: options.chromatic_aberration,
+            spectral: options.spectral,
+            seed: options.seed,
+        },
+    };
+    let pass = resume.as_ref().map_or(options.pass, |checkpoint| checkpoint.pass);
+    println!("seed: {}", settings.seed);
+
+    let mut scene = match &options.scene {
+        Some(path) => load_scene(path, settings.seed).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        None => initialize_scene(settings.seed),
+    };
+    if let Some(name) = options.camera.as_ref().filter(|_| !all_cameras) {
+        scene.active_camera = scene.find_camera(name).unwrap_or_else(|| {
+            eprintln!("error: the scene has no camera '{}'", name);
+            std::process::exit(1);
+        });
+    }
+    override_cameras(&mut scene, &options);
+
+    let viewport = Viewport {
         overlays_enabled: true,
+        undistorted_overlays: false,
         autofocus: true,
-        depth_pass: false,
-        normal_pass: false,
+        depth_pass: pass == Pass::Depth,
+        normal_pass: pass == Pass::Normal,
+        recording: false,
+        chromatic_aberration_strength: settings.chromatic_aberration_strength,
         sample_iter: 0,
         time: Time {
             start: Instant::now(),
-            prev: app::timestamp(),
+            prev: timestamp(),
             sum: Duration::new(0,0),
             framecount: 0,
         },
     };
 
-    let mut movement = Movement {
-        camera_movement: Vector3::new(0.0, 0.0, 0.0),
-        camera_rotation: cgmath::Matrix4::from_angle_z(cgmath::Rad(scene.cameras[0].rot.z))
-            * cgmath::Matrix4::from_angle_y(cgmath::Rad(scene.cameras[0].rot.y))
-            * cgmath::Matrix4::from_angle_x(cgmath::Rad(scene.cameras[0].rot.x)),
-        mouse_movement: Vector3::new(0.0, 0.0, 0.0),
-        moving: false,
-    };
+    if options.mode == Mode::Animation {
+        render_animation(scene, settings, &options, pass).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        });
+        return;
+    }
+
+    if options.mode == Mode::Coordinator {
+        let mut renderer = Renderer::new(scene, settings);
+        renderer.autofocus();
+        run_coordinator(&renderer, &options, pass).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        });
+        return;
+    }
+
+    if options.mode == Mode::Headless {
+        let mut stop = match &resume {
+            // A resumed render runs until what it was started with, unless told otherwise
+            Some(checkpoint) if options.stop.is_empty() => checkpoint.stop,
+            _ => options.stop,
+        };
+        if stop.is_empty() {
+            stop.samples = Some(64);
+        }
+
+        let mut renderer = Renderer::new(scene, settings);
+        if let Some(checkpoint) = &resume {
+            if checkpoint.scene_hash != scene_hash(&renderer.scene) {
+                eprintln!("error: the scene has changed since the checkpoint was saved");
+                std::process::exit(1);
+            }
+            let mut camera = checkpoint.camera.clone();
+            camera.name = renderer.camera().name.clone();
+            camera.object_id = renderer.camera().object_id;
+            camera.aperture = renderer.camera().aperture.clone();
+            renderer.set_camera(camera);
+        }
+
+        // Every camera to its own image, named after the camera
+        let cameras = if all_cameras {
+            (0..renderer.scene.cameras.len()).collect()
+        } else {
+            vec![renderer.scene.active_camera]
+        };
+        for camera in cameras {
+            let mut output = options.output.clone();
+            if all_cameras {
+                output = camera_output_path(&output, &renderer.scene.camera_label(camera));
+                println!("camera {}", renderer.scene.camera_label(camera));
+            }
+            renderer.set_active_camera(camera);
+            if resume.is_none() && viewport.autofocus {
+                renderer.autofocus();
+            }
+
+            render_headless(&mut renderer, &options, &output, pass, stop, resume.take()).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+            if interrupted() {
+                std::process::exit(130);
+            }
+        }
+        return;
+    }
+
+    run_viewer(&options, scene, settings, viewport);
+}
+
+// Camera settings given on the command line apply to every camera of the scene
+fn override_cameras(scene: &mut Scene, options: &Options) {
+    for camera in &mut scene.cameras {
+        if let Some(projection) = options.projection {
+            camera.projection = projection;
+        }
+        if let Some(stereo) = options.stereo {
+            camera.stereo = stereo;
+        }
+        if let Some(squeeze) = options.anamorphic_squeeze {
+            camera.anamorphic_squeeze = squeeze;
+        }
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_viewer(_options: &Options, _scene: Scene, _settings: RenderSettings, _viewport: Viewport) {
+    eprintln!("error: this build has no viewer, run with --headless or rebuild with the \"gui\" feature");
+    std::process::exit(1);
+}
+
+// Open a window and keep refining the image until it is closed, or until the
+// stop conditions are met, after which accumulation is frozen until the view changes
+#[cfg(feature = "gui")]
+fn run_viewer(options: &Options, mut scene: Scene, mut settings: RenderSettings, mut viewport: Viewport) {
+    use minifb::{Window, WindowOptions};
+    use simple_rust_cpu_raytracer::animation::Animation;
+    use simple_rust_cpu_raytracer::distortion::Distortion;
+    use simple_rust_cpu_raytracer::input::{handle_input, Key, ReplayInput};
+    use simple_rust_cpu_raytracer::movement::*;
+    use simple_rust_cpu_raytracer::watch::SceneWatcher;
+
+    let (mut width, mut height) = (settings.width, settings.height);
+    let mut output_buffer: Vec<u32> = vec![0; width * height];
+    let mut render_buffer: Vec<Col> = vec![Col::new(0.0, 0.0, 0.0); width * height];
+    let mut window = Window::new(
+        "",
+        width,
+        height,
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+
+    let mut movement = Movement::new(scene.camera());
     let mut keys_down: Vec<Key> = vec![];
+    let mut replay = options.replay_input.as_ref().map(|path| {
+        ReplayInput::load(path).unwrap_or_else(|e| {
+            eprintln!("error: could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+    let mut scene_watcher = options
+        .scene
+        .as_ref()
+        .map(|path| SceneWatcher::new(path, options.seed));
+    let mut progress = Progress::new(options.stop);
+
+    // A recorded camera path flies the camera from the start, then hands it over to the window
+    let mut playback = options.camera_path.as_ref().map(|path| {
+        let camera_path = CameraPath::load(path).unwrap_or_else(|e| {
+            eprintln!("error: could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        Animation {
+            cameras: vec![camera_path.animation(scene.active_camera)],
+            spheres: vec![],
+        }
+    });
+    let playback_start = Instant::now();
+    let mut recording: Option<(CameraPath, Instant)> = None;
 
     // Main loop
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        app::update_time(&mut window, &mut viewport.time, &viewport.sample_iter);
-
-        handle_input(
-            &mut window,
-            &mut viewport,
-            &mut scene.cameras[0],
-            &mut render_buffer,
-            &mut movement,
-            &mut keys_down,
-            &WIDTH,
-            &HEIGHT,
-        );
+    while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
+        if let Some(title) = update_time(&mut viewport.time, &viewport.sample_iter) {
+            let mut title = title;
+            if !progress.conditions.is_empty() {
+                title = format!("{}   {}", title, progress.status());
+            }
+            if scene.cameras.len() > 1 {
+                title = format!(
+                    "{}   camera {} ({}/{})",
+                    title,
+                    scene.camera_label(scene.active_camera),
+                    scene.active_camera + 1,
+                    scene.cameras.len()
+                );
+            }
+            if recording.is_some() {
+                title += "   recording";
+            }
+            if viewport.chromatic_aberration_strength > 0.0 {
+                title = format!("{}   chromatic aberration {:.2}", title, viewport.chromatic_aberration_strength);
+            }
+            window.set_title(&title);
+        }
+
+        // Follow the window size, restarting the accumulation
+        let (window_width, window_height) = window.get_size();
+        if (window_width, window_height) != (width, height) && window_width > 0 && window_height > 0 {
+            width = window_width;
+            height = window_height;
+            settings.width = width;
+            settings.height = height;
+
+            output_buffer = vec![0; width * height];
+            render_buffer = vec![Col::new(0.0, 0.0, 0.0); width * height];
+            viewport.sample_iter = 0;
+        }
+
+        // Hot-reload the scene file, keeping the current camera pose
+        if let Some(result) = scene_watcher.as_mut().and_then(|watcher| watcher.poll()) {
+            match result {
+                Ok(mut new_scene) => {
+                    override_cameras(&mut new_scene, options);
+                    if scene.active_camera < new_scene.cameras.len() {
+                        new_scene.active_camera = scene.active_camera;
+                        new_scene.camera_mut().pos = scene.camera().pos;
+                        new_scene.camera_mut().rot = scene.camera().rot;
+                    }
+                    scene = new_scene;
+                    movement.camera_rotation = Movement::new(scene.camera()).camera_rotation;
+
+                    render_buffer = vec![Col::new(0.0, 0.0, 0.0); width * height];
+                    viewport.sample_iter = 0;
+                    println!("Reloaded scene");
+                }
+                Err(e) => eprintln!("error: {} (keeping the previous scene)", e),
+            }
+        }
+
+        // Play back recorded input first, then hand over to the window
+        match replay.as_mut().filter(|replay| !replay.finished()) {
+            Some(replay) => {
+                handle_input(
+                    replay,
+                    &mut viewport,
+                    &mut scene,
+                    &mut render_buffer,
+                    &mut movement,
+                    &mut keys_down,
+                    (width, height),
+                );
+                replay.advance();
+            }
+            None => handle_input(
+                &window,
+                &mut viewport,
+                &mut scene,
+                &mut render_buffer,
+                &mut movement,
+                &mut keys_down,
+                (width, height),
+            ),
+        }
 
-        let image_plane_size = 2.0 * rad(scene.cameras[0].fov / 2.0).tan();
+        // The camera path overrides the camera, but the window's other keys still work
+        if let Some(animation) = &playback {
+            let time = playback_start.elapsed().as_secs_f32();
+            animation.apply(&mut scene, time);
+            movement.camera_rotation = Movement::new(scene.camera()).camera_rotation;
+            render_buffer = vec![Col::new(0.0, 0.0, 0.0); width * height];
+            viewport.sample_iter = 0;
+            if time >= animation.duration() {
+                playback = None;
+                println!("Camera path finished");
+            }
+        }
+
+        let image_plane_size = 2.0 * rad(scene.camera().fov / 2.0).tan();
 
         autofocus(
             viewport.autofocus,
-            WIDTH as f32,
-            HEIGHT as f32,
+            width as f32,
+            height as f32,
             &mut scene,
             image_plane_size,
             &movement,
         );
 
-        let jitter_size = scene.cameras[0].aperture_radius
-            * 2.0
-            * (1.0 - 1.0 / (scene.cameras[0].focal_length + 0.5));
-        let pixel_size: f32 = 1.0 / WIDTH as f32 * image_plane_size / 2.0;
-
-        // Iterate over pixels
-        render_buffer
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, pixel)| {
-                let mut rng = thread_rng();
-                // Create ray from camera
-                let (ray, chromatic_aberration_len) = camera_ray(
-                    i,
-                    &scene,
-                    image_plane_size,
-                    jitter_size,
-                    pixel_size,
-                    WIDTH as f32,
-                    HEIGHT as f32,
-                    &movement,
-                    &mut rng,
-                    CHROMATIC_ABERRATION_STRENGTH
-                );
+        // Record the camera after autofocus, so the path has the focus that was rendered
+        match &mut recording {
+            Some((camera_path, start)) if viewport.recording => {
+                camera_path.record(start.elapsed().as_secs_f32(), scene.camera());
+            }
+            Some((camera_path, _)) => {
+                save_camera_path(camera_path, &options.record_path);
+                recording = None;
+            }
+            None if viewport.recording => {
+                let mut camera_path = CameraPath::default();
+                camera_path.record(0.0, scene.camera());
+                recording = Some((camera_path, Instant::now()));
+                println!("Recording camera path, press R again to stop");
+            }
+            None => (),
+        }
 
-                // Trace ray
-                let col = intersect_spheres(
-                    3,
-                    10,
-                    0,
-                    0,
-                    &scene,
-                    viewport.depth_pass,
-                    viewport.normal_pass,
-                    &scene.spheres,
-                    ObjectID::from(0),
-                    &ray,
-                    &mut rng,
-                );
+        if viewport.sample_iter == 0 {
+            progress.restart();
+        }
 
-                if CHROMATIC_ABERRATION_STRENGTH > 0.0 {
-                    let cr = chromatic_aberration_len/2.0 + 0.5;
-                    let col = col * Col::from_hue(cr) * (1.0 / Col::from_hue(cr).luminance());
-                }
-                
-                // Update render buffer with result
-                *pixel += col;
-            });
+        // Changed with O and P
+        settings.chromatic_aberration_strength = viewport.chromatic_aberration_strength;
+        if !progress.done() {
+            render_sample(
+                &mut render_buffer,
+                &scene,
+                viewport.pass(),
+                viewport.sample_iter,
+                &movement,
+                &settings,
+            );
 
-        viewport.sample_iter += 1;
+            viewport.sample_iter += 1;
+            progress.add_sample(&render_buffer, viewport.sample_iter);
+        }
 
         // Update frame buffer with render buffer
-        for (col_1, col_2) in render_buffer.iter().zip(output_buffer.iter_mut()) {
-            let col = Col::new(
-                clamp_max(col_1.r / viewport.sample_iter as f32, 1.0),
-                clamp_max(col_1.g / viewport.sample_iter as f32, 1.0),
-                clamp_max(col_1.b / viewport.sample_iter as f32, 1.0),
-            );
+        update_output_buffer(&render_buffer, &mut output_buffer, viewport.sample_iter);
 
-            *col_2 = col_to_rgb_u32(col);
+        // Draw overlays, which are drawn for a single eye
+        if viewport.overlays_enabled && scene.camera().stereo == Stereo::Mono {
+            let mut camera = scene.camera().clone();
+            if viewport.undistorted_overlays {
+                camera.distortion = Distortion::default();
+            }
+            for wireframe in &scene.wireframes {
+                wireframe.render(&mut output_buffer, &camera, width, height);
+            }
         }
+        letterbox(&mut output_buffer, width, height, scene.camera().anamorphic_squeeze);
 
-        // Draw overlays
-        if viewport.overlays_enabled {
-            for wireframe in &mut scene.wireframes {
-                wireframe.render(&mut output_buffer, &scene.cameras[0], &WIDTH, &HEIGHT);
+        // Update window
+        window.update_with_buffer(&output_buffer, width, height).unwrap();
+    }
+
+    if let Some((camera_path, _)) = &recording {
+        save_camera_path(camera_path, &options.record_path);
+    }
+}
+
+#[cfg(feature = "gui")]
+fn save_camera_path(camera_path: &CameraPath, path: &Path) {
+    match camera_path.save(path) {
+        Ok(()) => println!(
+            "Saved {} frames of camera path to {}",
+            camera_path.frames.len(),
+            path.display()
+        ),
+        Err(e) => eprintln!("error: could not write {}: {}", path.display(), e),
+    }
+}
+
+// Render without a window until the stop conditions are met, then write the result to disk.
+// Progress is saved to the checkpoint file, if there is one, every so often and at the end.
+// On Ctrl-C the render stops after the current sample and saves a final checkpoint.
+fn render_headless(
+    renderer: &mut Renderer,
+    options: &Options,
+    output: &Path,
+    pass: Pass,
+    stop: StopConditions,
+    resume: Option<Checkpoint>,
+) -> Result<(), String> {
+    let (width, height) = (renderer.settings.width, renderer.settings.height);
+    let mut render_buffer = vec![Col::new(0.0, 0.0, 0.0); width * height];
+    let mut sample_iter = 0;
+    let mut progress = Progress::new(stop);
+
+    if let Some(checkpoint) = resume {
+        sample_iter = checkpoint.samples;
+        progress.resume(&checkpoint.render_buffer, &checkpoint.half_buffer, sample_iter);
+        render_buffer = checkpoint.render_buffer;
+        println!("resuming at sample {}", sample_iter);
+    }
+
+    let checkpoint_path = options.checkpoint.clone().or_else(|| options.resume.clone());
+    // Checkpoints hold the even samples too, so that a resumed render can stop on noise
+    if checkpoint_path.is_some() {
+        progress.estimate_noise = true;
+    }
+    let save_checkpoint = |path: &Path, render_buffer: &[Col], progress: &Progress| {
+        Checkpoint {
+            settings: renderer.settings.clone(),
+            pass,
+            samples: progress.samples,
+            stop: StopConditions {
+                time_budget: progress
+                    .conditions
+                    .time_budget
+                    .map(|budget| budget.saturating_sub(progress.start.elapsed())),
+                ..progress.conditions
+            },
+            scene_hash: scene_hash(&renderer.scene),
+            camera: renderer.camera().clone(),
+            render_buffer: render_buffer.to_vec(),
+            half_buffer: progress.estimator.half_buffer.clone(),
+        }
+        .save(path)
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+    };
+
+    catch_interrupt();
+    let mut last_checkpoint = Instant::now();
+    while !progress.done() && !interrupted() {
+        renderer.render(pass, &mut render_buffer, sample_iter, 1);
+        sample_iter += 1;
+
+        progress.add_sample(&render_buffer, sample_iter);
+        println!("{}", progress.status());
+
+        if let Some(path) = &checkpoint_path {
+            if last_checkpoint.elapsed() >= options.checkpoint_interval {
+                save_checkpoint(path, &render_buffer, &progress)?;
+                last_checkpoint = Instant::now();
             }
         }
+    }
 
-        // Update window
-        window.update_with_buffer(&output_buffer, WIDTH, HEIGHT).unwrap();
+    if interrupted() {
+        let path = checkpoint_path.unwrap_or_else(|| output.with_extension("checkpoint"));
+        save_checkpoint(&path, &render_buffer, &progress)?;
+        println!("Interrupted, resume with --resume {}", path.display());
+    } else if let Some(path) = &checkpoint_path {
+        save_checkpoint(path, &render_buffer, &progress)?;
+    }
+
+    save_image(output, &render_buffer, sample_iter, width, height, renderer.camera().anamorphic_squeeze)
+        .map_err(|e| format!("could not write {}: {}", output.display(), e))
+}
+
+// The output file for one of several cameras: render.png becomes render_main.png
+fn camera_output_path(output: &Path, camera: &str) -> PathBuf {
+    let stem = output.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+    let mut file_name = format!("{}_{}", stem, camera);
+    if let Some(extension) = output.extension() {
+        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+    output.with_file_name(file_name)
+}
+
+// Render the frames of the scene's animation, each to its own image
+fn render_animation(mut scene: Scene, settings: RenderSettings, options: &Options, pass: Pass) -> Result<(), String> {
+    // A recorded camera path takes over the active camera
+    let active_camera = scene.active_camera;
+    if let Some(path) = &options.camera_path {
+        let camera_path = CameraPath::load(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        scene.animation.cameras.retain(|animation| animation.camera != active_camera);
+        scene.animation.cameras.push(camera_path.animation(active_camera));
+    }
+
+    let animation = &scene.animation;
+    if animation.is_empty() {
+        return Err("the scene has no animation and no --camera-path was given".into());
+    }
+    let mut stop = options.stop;
+    if stop.is_empty() {
+        stop.samples = Some(64);
+    }
+
+    let frame_count = (animation.duration() * options.fps).floor() as u32 + 1;
+    let (first, last) = options.frames.unwrap_or((0, frame_count - 1));
+    if first >= frame_count {
+        return Err(format!("the animation only has {} frames", frame_count));
+    }
+    let last = last.min(frame_count - 1);
+    // A keyed focal length is left alone, otherwise every frame is focused anew
+    let autofocus = !animation.animates_focal_length(active_camera);
+
+    let start = Instant::now();
+    for frame in first..=last {
+        let mut frame_scene = scene.clone();
+        animation.apply(&mut frame_scene, frame as f32 / options.fps);
+        let mut renderer = Renderer::new(frame_scene, settings.clone());
+        if autofocus {
+            renderer.autofocus();
+        }
+
+        let mut render_buffer = vec![Col::new(0.0, 0.0, 0.0); settings.width * settings.height];
+        let mut progress = Progress::new(stop);
+        while !progress.done() {
+            renderer.render(pass, &mut render_buffer, progress.samples, 1);
+            progress.add_sample(&render_buffer, progress.samples + 1);
+        }
+
+        let path = frame_path(&options.output, frame);
+        let squeeze = renderer.camera().anamorphic_squeeze;
+        save_image(&path, &render_buffer, progress.samples, settings.width, settings.height, squeeze)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+
+        let (done, total) = (frame - first + 1, last - first + 1);
+        let eta = start.elapsed().mul_f32((total - done) as f32 / done as f32);
+        println!(
+            "frame {}/{}   {}   ETA {}",
+            done,
+            total,
+            path.display(),
+            format_duration(eta)
+        );
+    }
+    Ok(())
+}
+
+// Hand out tiles to workers until the image has all its samples, then write it to disk
+fn run_coordinator(renderer: &Renderer, options: &Options, pass: Pass) -> Result<(), String> {
+    let stop = options.stop;
+    if stop.time_budget.is_some() || stop.noise_threshold.is_some() {
+        return Err("--coordinator only supports --spp as a stop condition".into());
+    }
+    let samples = stop.samples.unwrap_or(64);
+    let (width, height) = (renderer.settings.width, renderer.settings.height);
+    let jobs = Job::split(
+        width,
+        height,
+        options.tile_size,
+        samples,
+        options.chunk_samples.unwrap_or(samples),
+    );
+
+    let listener = TcpListener::bind(&options.address)
+        .map_err(|e| format!("could not listen on {}: {}", options.address, e))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    println!("listening on {}", address);
+
+    let start = Instant::now();
+    let on_event = |event: Event| match event {
+        Event::WorkerJoined(worker) => println!("worker {} joined", worker),
+        Event::JobDone { worker, done, total } => {
+            let eta = start.elapsed().mul_f32((total - done) as f32 / done as f32);
+            println!(
+                "job {}/{} from {}   {:.0}%   ETA {}",
+                done,
+                total,
+                worker,
+                done as f32 / total as f32 * 100.0,
+                format_duration(eta)
+            );
+        }
+        Event::WorkerLost(worker, e) => {
+            eprintln!("worker {} dropped out: {} (its job goes to another worker)", worker, e)
+        }
+    };
+
+    let render_buffer = distributed::coordinate(
+        &listener,
+        &renderer.scene,
+        &renderer.settings,
+        pass,
+        jobs,
+        distributed::WORKER_TIMEOUT,
+        &on_event,
+    )
+    .map_err(|e| e.to_string())?;
+    println!("done in {}", format_duration(start.elapsed()));
+
+    save_image(&options.output, &render_buffer, samples, width, height, renderer.camera().anamorphic_squeeze)
+        .map_err(|e| format!("could not write {}: {}", options.output.display(), e))
+}
+
+// Render tiles for a coordinator until it has all it needs
+fn run_worker(address: &str) {
+    let stream = distributed::connect(address, Duration::from_secs(30)).unwrap_or_else(|e| {
+        eprintln!("error: could not connect to {}: {}", address, e);
+        std::process::exit(1);
+    });
+    println!("connected to {}", address);
+
+    match distributed::work(stream) {
+        Ok(jobs) => println!("done after {} jobs", jobs),
+        Err(e) => {
+            eprintln!("error: lost the coordinator: {}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -1,70 +1,296 @@
-use crate::helpers::{clamp, uv, Col, ObjectID};
+use crate::helpers::{clamp, rad, uv, Col, ObjectID};
 use crate::intersect::*;
 use crate::movement::*;
-use crate::scene::{Ray, Scene, Sphere};
+use crate::scene::{Camera, Projection, Ray, Scene, Sphere};
 use crate::skybox::sky_box;
+use crate::spectrum::Wavelengths;
 use cgmath::{dot, InnerSpace, Vector3};
 use cgmath::{EuclideanSpace, Matrix4, Point3, Vector4};
 use ordered_float::OrderedFloat;
+use crate::rng::PixelRng;
 use rand::Rng;
-use rand::prelude::ThreadRng;
 
-// Create ray from camera
+// How much the image magnification and the focal length change across the spectrum, at a
+// chromatic aberration strength of 1
+const LATERAL_CHROMATIC_ABERRATION: f32 = 0.01;
+const LONGITUDINAL_CHROMATIC_ABERRATION: f32 = 0.1;
+
+// What a camera ray is for, apart from its pixel and random numbers: the eye it's seen
+// with, -1 for the left, 1 for the right and 0 for mono, and the single wavelength it
+// carries for chromatic aberration, from -1 at the red end of the spectrum to 1 at the
+// blue end
+#[derive(Debug, Clone, Copy)]
+pub struct CameraSample {
+    pub eye: f32,
+    pub wavelength: f32,
+    pub chromatic_aberration_strength: f32,
+}
+
+// Create ray from camera, with the share of its light that vignetting lets through
 pub fn camera_ray(
     i: usize,
     scene: &Scene,
-    image_plane_size: f32,
-    jitter_size: f32,
-    pixel_size: f32,
     width: f32,
     height: f32,
     movement: &Movement,
-    rng: &mut ThreadRng,
-    chromatic_aberration_strength: f32,
-) -> (Ray, f32) {
-    let jitter_angle = rng.gen_range(0.0..1.0) * std::f32::consts::PI * 2.0;
-    let jitter_length = (rng.gen_range(0.0..1.0) as f32).sqrt();
-    let jitter_x = jitter_length * jitter_angle.cos();
-    let jitter_z = jitter_length * jitter_angle.sin();
+    rng: &mut PixelRng,
+    sample: &CameraSample,
+) -> Option<(Ray, f32)> {
+    let camera = scene.camera();
+    let CameraSample {
+        eye,
+        wavelength,
+        chromatic_aberration_strength,
+    } = *sample;
+    let image_plane_size = 2.0 * rad(camera.fov / 2.0).tan();
+    // Pixels are square, and the image plane size spans the image height
+    let pixel_size = 1.0 / height * image_plane_size / 2.0;
+
+    let (jitter_x, jitter_z) = camera.aperture.sample(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+    // An anamorphic lens is narrower across than it is high, so out of focus highlights are
+    // ovals. They are narrower still in the squeezed render, and stretching the image back out
+    // when it is saved rounds them off to as tall as the squeeze times their width
+    let squeeze = camera.anamorphic_squeeze;
+    let jitter_x = jitter_x / squeeze;
 
     
     let aperture_jitter =
-    Vector3::new(jitter_x, 0.0, jitter_z) * 2.0 * scene.cameras[0].aperture_radius;
+    Vector3::new(jitter_x, 0.0, jitter_z) * 2.0 * scene.camera().aperture_radius;
     
-    let chromatic_aberration_jitter_length: f32 = rng.gen_range(-1.0..1.0);
-    let chromatic_aberration_jitter =
-        Vector3::new(0.0, chromatic_aberration_jitter_length * chromatic_aberration_strength, 0.0);
+    // Chromatic aberration: bluer light is magnified less, spreading colors apart towards
+    // the edges of the image, and focuses nearer the lens
+    let magnification = 1.0 - wavelength * chromatic_aberration_strength * LATERAL_CHROMATIC_ABERRATION;
+    let focal_length =
+        camera.focal_length * (1.0 - wavelength * chromatic_aberration_strength * LONGITUDINAL_CHROMATIC_ABERRATION);
 
-    let anti_aliasing_jitter = Vector3::new(
-        rng.gen_range(-1.0..1.0) * pixel_size,
-        0.0,
-        rng.gen_range(-1.0..1.0) * pixel_size,
-    );
+    let (pixel_jitter_x, pixel_jitter_z) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let anti_aliasing_jitter = Vector3::new(pixel_jitter_x * pixel_size * squeeze, 0.0, pixel_jitter_z * pixel_size);
 
-    let dir = {
+    if camera.projection != Projection::Perspective {
+        // Anywhere within the pixel, in units of the image height like projection_ray takes
         let uv = uv(width * height - i as f32 - 1.0, width, height);
+        let x = -(uv.x * width - width / 2.0) / height + pixel_jitter_x * 0.5 / height + camera.shift.x;
+        let y = (uv.y * height - height / 2.0) / height + pixel_jitter_z * 0.5 / height + camera.shift.y;
+        let (x, y) = camera.distortion.undistort(x, y)?;
+        let (origin, dir) = projection_ray(camera, x * magnification * squeeze, y * magnification, width * squeeze / height)?;
+        let vignetting = vignetting(camera, x, y, dir.y, jitter_x, jitter_z);
+        let (origin, dir) = if eye != 0.0 {
+            eye_ray(camera, eye, origin, dir)
+        } else {
+            (origin, dir)
+        };
+
+        // Thin lens: rays from anywhere on the lens meet again at the focal length, or on the
+        // tilted plane of focus, unless they head away from it
+        let lens = origin + lens_offset(dir, jitter_x, jitter_z) * 2.0 * camera.aperture_radius;
+        let dir = match focus_distance(camera, origin, dir, focal_length) {
+            Some(distance) => origin + dir * distance - lens,
+            None => dir,
+        };
+        let origin = lens;
+        let dir = (movement.camera_rotation * dir.extend(0.0)).truncate();
+        let origin = (movement.camera_rotation * origin.extend(0.0)).truncate();
+
+        return Some((
+            Ray {
+                pos: camera.pos + origin,
+                dir: dir.normalize(),
+                from_wormhole: false,
+                from_object_id: ObjectID::from(0),
+            },
+            vignetting,
+        ));
+    }
 
-        Vector3::new(
-            ((uv.x * width - width as f32 / 2.0) / height as f32) * -image_plane_size
-                + jitter_x * jitter_size,
-            1.0,
-            ((uv.y * height - height as f32 / 2.0) / height as f32) * image_plane_size
-                + jitter_z * jitter_size,
-        ) - aperture_jitter
-            + anti_aliasing_jitter
+    let uv = uv(width * height - i as f32 - 1.0, width, height);
+    // In image heights from the center of the lens, x to the right and y up, where the lens
+    // distortion moved the point on the image from
+    let (x, y) = camera.distortion.undistort(
+        -(uv.x * width - width / 2.0) / height + camera.shift.x,
+        (uv.y * height - height / 2.0) / height + camera.shift.y,
+    )?;
+    let center_dir = Vector3::new(x * image_plane_size * squeeze, 1.0, y * image_plane_size);
+    let vignetting = vignetting(camera, x, y, 1.0 / center_dir.magnitude(), jitter_x, jitter_z);
+
+    // The aperture jitter brings rays together at the focal length in front of the lens, or
+    // wherever the pixel sees the tilted plane of focus
+    let magnified = Vector3::new(center_dir.x * magnification, 1.0, center_dir.z * magnification);
+    let focus = focus_distance(camera, Vector3::new(0.0, 0.0, 0.0), magnified, focal_length).unwrap_or(f32::INFINITY);
+    let jitter_size = camera.aperture_radius * 2.0 * (1.0 - 1.0 / (focus + 0.5));
+
+    let dir = Vector3::new(
+        x * image_plane_size * magnification * squeeze + jitter_x * jitter_size,
+        1.0,
+        y * image_plane_size * magnification + jitter_z * jitter_size,
+    ) - aperture_jitter
+        + anti_aliasing_jitter;
+
+    let combined_jitter = aperture_jitter + anti_aliasing_jitter;
+    let (combined_jitter, dir) = if eye != 0.0 {
+        eye_ray(camera, eye, combined_jitter, dir)
+    } else {
+        (combined_jitter, dir)
     };
 
     let dir = (movement.camera_rotation * dir.extend(0.0)).truncate();
-
-    let combined_jitter = aperture_jitter + anti_aliasing_jitter + chromatic_aberration_jitter;
     let combined_jitter = (movement.camera_rotation * combined_jitter.extend(0.0)).truncate();
 
-    return (Ray {
-        pos: scene.cameras[0].pos + combined_jitter,
+    return Some((Ray {
+        pos: camera.pos + combined_jitter,
         dir: dir.normalize(),
         from_wormhole: false,
         from_object_id: ObjectID::from(0),
-    },chromatic_aberration_jitter_length);
+    }, vignetting));
+}
+
+// The share of the light through a point on the lens that reaches a point on the image, in
+// image heights from the center. Light reaching the edges of the image comes in at an angle
+// to the sensor, which spreads it out (the cos^4 law), and part of the lens is hidden
+// behind the lens barrel there.
+fn vignetting(camera: &Camera, x: f32, y: f32, cos_angle: f32, jitter_x: f32, jitter_z: f32) -> f32 {
+    let barrel_x = jitter_x - x * camera.optical_vignetting;
+    let barrel_z = jitter_z - y * camera.optical_vignetting;
+    if barrel_x * barrel_x + barrel_z * barrel_z > 1.0 {
+        return 0.0;
+    }
+    1.0 - camera.vignetting * (1.0 - cos_angle.max(0.0).powi(4))
+}
+
+// The plane of focus in camera space is where points have a dot product of the focal length
+// with this: y = focal_length + z * tan(tilt.x) + x * tan(tilt.y), leaning back by tilt.x
+// and turned to the right by tilt.y
+pub fn focal_plane_normal(camera: &Camera) -> Vector3<f32> {
+    Vector3::new(-rad(camera.tilt.y).tan(), 1.0, -rad(camera.tilt.x).tan())
+}
+
+// How far along a ray from a point in camera space the plane of focus is, or None if the
+// ray never gets there. Without tilt it is the focal length, all the way around a fisheye.
+fn focus_distance(camera: &Camera, origin: Vector3<f32>, dir: Vector3<f32>, focal_length: f32) -> Option<f32> {
+    if !camera.tilted() {
+        return Some(focal_length);
+    }
+    let normal = focal_plane_normal(camera);
+    let distance = (focal_length - dot(origin, normal)) / dot(dir, normal);
+    if distance > 0.0 && distance.is_finite() {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+// Where a ray through a point on the image starts, relative to the camera, and which way
+// it heads, in camera space: x to the right, y forward and z up. The point is measured in
+// image heights from the image center, x to the right and y up. None for points outside
+// the image circle of a fisheye.
+pub fn projection_ray(camera: &Camera, x: f32, y: f32, aspect: f32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let origin = Vector3::new(0.0, 0.0, 0.0);
+    match camera.projection {
+        Projection::Perspective => {
+            let image_plane_size = 2.0 * rad(camera.fov / 2.0).tan();
+            Some((origin, Vector3::new(x * image_plane_size, 1.0, y * image_plane_size).normalize()))
+        }
+        Projection::Orthographic => Some((
+            Vector3::new(x, 0.0, y) * camera.ortho_height,
+            Vector3::new(0.0, 1.0, 0.0),
+        )),
+        Projection::Fisheye => {
+            // The angle from the view direction grows linearly with the distance from the
+            // center, reaching half the field of view at the top and bottom edges
+            let theta = (x * x + y * y).sqrt() * rad(camera.fov);
+            if theta > std::f32::consts::PI {
+                return None;
+            }
+            let phi = y.atan2(x);
+            Some((
+                origin,
+                Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()),
+            ))
+        }
+        Projection::Equirectangular => {
+            let longitude = x / aspect * 2.0 * std::f32::consts::PI;
+            let latitude = y * std::f32::consts::PI;
+            Some((
+                origin,
+                Vector3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.cos() * longitude.cos(),
+                    latitude.sin(),
+                ),
+            ))
+        }
+    }
+}
+
+// The inverse of projection_ray: where a point in camera space shows up on the image, in
+// image heights from the image center. None if the camera can't see it.
+pub fn project(camera: &Camera, point: Vector3<f32>, aspect: f32) -> Option<(f32, f32)> {
+    match camera.projection {
+        Projection::Perspective => {
+            if point.y <= 0.0 {
+                return None;
+            }
+            let image_plane_size = 2.0 * rad(camera.fov / 2.0).tan();
+            Some((point.x / point.y / image_plane_size, point.z / point.y / image_plane_size))
+        }
+        Projection::Orthographic => {
+            if point.y <= 0.0 {
+                return None;
+            }
+            Some((point.x / camera.ortho_height, point.z / camera.ortho_height))
+        }
+        Projection::Fisheye => {
+            let theta = (point.x * point.x + point.z * point.z).sqrt().atan2(point.y);
+            let r = theta / rad(camera.fov);
+            let phi = point.z.atan2(point.x);
+            Some((r * phi.cos(), r * phi.sin()))
+        }
+        Projection::Equirectangular => {
+            let longitude = point.x.atan2(point.y);
+            let latitude = point.z.atan2((point.x * point.x + point.y * point.y).sqrt());
+            Some((
+                longitude / (2.0 * std::f32::consts::PI) * aspect,
+                latitude / std::f32::consts::PI,
+            ))
+        }
+    }
+}
+
+// Move a ray from the camera to one of the eyes, -1 for the left and 1 for the right.
+// The eyes look in parallel, but with their images shifted so that they line up at the
+// convergence distance (off-axis stereo): the ray heads for where the camera's own ray
+// meets the plane that far ahead. Rays that never get there, out to the sides of a
+// fisheye, stay parallel.
+//
+// Panoramas are omni-directional stereo instead: the eyes circle the camera position,
+// always side by side as seen from the direction being looked in, with parallel views.
+fn eye_ray(camera: &Camera, eye: f32, origin: Vector3<f32>, dir: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let half_distance = eye * camera.interocular_distance / 2.0;
+
+    if camera.projection == Projection::Equirectangular {
+        let longitude = dir.x.atan2(dir.y);
+        let right = Vector3::new(longitude.cos(), -longitude.sin(), 0.0);
+        return (origin + right * half_distance, dir);
+    }
+
+    let eye_offset = Vector3::new(half_distance, 0.0, 0.0);
+    let dir = if camera.convergence > 0.0 && dir.y > 0.0 {
+        dir * (camera.convergence / dir.y) - eye_offset
+    } else {
+        dir
+    };
+    (origin + eye_offset, dir.normalize())
+}
+
+// A point on a lens facing along dir, for a point on the unit disk
+fn lens_offset(dir: Vector3<f32>, disk_x: f32, disk_y: f32) -> Vector3<f32> {
+    let up = if dir.z.abs() < 0.999 {
+        Vector3::new(0.0, 0.0, 1.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let right = dir.cross(up).normalize();
+    let up = right.cross(dir);
+    right * disk_x + up * disk_y
 }
 
 // Create ray from camera with no jittering (used for autofocus)
@@ -75,43 +301,67 @@ pub fn camera_ray_simple(
     width: f32,
     height: f32,
     movement: &Movement,
-) -> Ray {
-    let dir = {
-        let uv = uv(width * height - i - 1.0, width, height);
-
-        Vector3::new(
-            ((uv.x * width - width as f32 / 2.0) / height as f32) * -image_plane_size,
-            1.0,
-            ((uv.y * height - height as f32 / 2.0) / height as f32) * image_plane_size,
-        )
-    };
+) -> Option<Ray> {
+    let camera = scene.camera();
+    let squeeze = camera.anamorphic_squeeze;
+    let uv = uv(width * height - i - 1.0, width, height);
+    let (x, y) = camera.distortion.undistort(
+        -(uv.x * width - width / 2.0) / height + camera.shift.x,
+        (uv.y * height - height / 2.0) / height + camera.shift.y,
+    )?;
+
+    if camera.projection != Projection::Perspective {
+        let (origin, dir) = projection_ray(camera, x * squeeze, y, width * squeeze / height)?;
+
+        return Some(Ray {
+            pos: camera.pos + (movement.camera_rotation * origin.extend(0.0)).truncate(),
+            dir: (movement.camera_rotation * dir.extend(0.0)).truncate(),
+            from_wormhole: false,
+            from_object_id: ObjectID::from(0),
+        });
+    }
+
+    let dir = Vector3::new(x * image_plane_size * squeeze, 1.0, y * image_plane_size);
 
     let dir = (movement.camera_rotation * dir.extend(0.0)).truncate();
 
-    return Ray {
-        pos: scene.cameras[0].pos,
+    return Some(Ray {
+        pos: camera.pos,
         dir: dir.normalize(),
         from_wormhole: false,
         from_object_id: ObjectID::from(0),
-    };
+    });
+}
+
+// What stays the same for every bounce along a path
+#[derive(Debug, Clone, Copy)]
+pub struct Trace {
+    pub max_bounces: i32,
+    pub max_wormhole_bounces: i32,
+    pub depth_pass: bool,
+    pub normal_pass: bool,
+    // The wavelengths the channels of the returned color are for, when rendering spectrally
+    pub wavelengths: Option<Wavelengths>,
 }
 
 pub fn intersect_spheres(
-    max_bounces: i32,
-    max_wormhole_bounces: i32,
+    trace: &Trace,
     bounce_count: i32,
     wormhole_bounce_count: i32,
     scene: &Scene,
-    depth_pass: bool,
-    normal_pass: bool,
-    spheres: &[Sphere],
-    // spheres: &Vec<Rc<Sphere>>,
     from_object_id: ObjectID,
     ray: &Ray,
-    rng: &mut ThreadRng,
+    rng: &mut PixelRng,
 ) -> Col {
+    // The scene's colors, as seen at the wavelengths
+    let spectrum = |col: Col| match trace.wavelengths {
+        Some(wavelengths) => wavelengths.spectrum(col),
+        None => col,
+    };
+
     let mut col = sky_box(scene, ray);
 
+    let spheres = &scene.spheres;
     let closest: Option<(usize, f32)> = spheres
         .iter()
         .enumerate()
@@ -136,15 +386,15 @@ pub fn intersect_spheres(
         })
         .min_by_key(|(_, distance)| OrderedFloat(*distance));
 
-    if depth_pass {
-        if bounce_count < max_bounces {
+    if trace.depth_pass {
+        if bounce_count < trace.max_bounces {
             if let Some((_, distance)) = closest {
                 let d = 1.0 / (distance + 1.0).sqrt();
                 col = Col::new(d, d, d).clamp(0.0, 1.0);
             }
         }
         return col;
-    } else if normal_pass {
+    } else if trace.normal_pass {
         // Normal pass
         if let Some((i, t)) = closest {
             let bounce_point = ray.pos + ray.dir * t;
@@ -153,7 +403,7 @@ pub fn intersect_spheres(
             // Normal at intersection point
             let n = (bounce_point - bounce_sphere.pos).normalize();
 
-            let camera = &scene.cameras[0];
+            let camera = scene.camera();
             let rot = cgmath::Matrix4::from_angle_z(cgmath::Rad(camera.rot.z))
                 * cgmath::Matrix4::from_angle_y(cgmath::Rad(camera.rot.y))
                 * cgmath::Matrix4::from_angle_x(cgmath::Rad(camera.rot.x));
@@ -172,12 +422,14 @@ pub fn intersect_spheres(
         }
     }
 
+    col = spectrum(col);
+
     // Bounce:
     // Make new ray from old ray, bounce_point, and bounce_sphere
     // Recursively call intersect_spheres with new ray
 
     let should_terminate =
-        bounce_count > max_bounces || wormhole_bounce_count > max_wormhole_bounces;
+        bounce_count > trace.max_bounces || wormhole_bounce_count > trace.max_wormhole_bounces;
 
     if !should_terminate {
         if let Some((i, t)) = closest {
@@ -204,7 +456,18 @@ pub fn intersect_spheres(
             let cos_x = -dot(n, d);
 
             let x = 1.0 - cos_x;
-            let fresnel = clamp(r0 + (1.0 - r0) * x.powi(5), 0.0, 1.0);
+            let schlick = |r0: f32| clamp(r0 + (1.0 - r0) * x.powi(5), 0.0, 1.0);
+            // Glass reflects a little more of the bluer wavelengths
+            let fresnel = match trace.wavelengths {
+                Some(wavelengths) => {
+                    let r0 = wavelengths.glass_ior().map(|n2| ((n1 - n2) / (n1 + n2)).powi(2));
+                    Col::new(schlick(r0[0]), schlick(r0[1]), schlick(r0[2]))
+                }
+                None => {
+                    let fresnel = schlick(r0);
+                    Col::new(fresnel, fresnel, fresnel)
+                }
+            };
 
             // BRDF
             let diffuse = n + Vector3::new(
@@ -224,18 +487,14 @@ pub fn intersect_spheres(
                         + bounce_sphere.material.wormhole_params.wormhole_offset * wormhole_factor,
                     dir: ray.dir,
                     from_wormhole: true,
-                    from_object_id: bounce_sphere.object_id.clone(),
+                    from_object_id: bounce_sphere.object_id,
                 };
 
                 col = intersect_spheres(
-                    max_bounces,
-                    max_wormhole_bounces,
+                    trace,
                     bounce_count,
                     wormhole_bounce_count + 1,
-                    &scene,
-                    depth_pass,
-                    normal_pass,
-                    &spheres,
+                    scene,
                     bounce_sphere.object_id,
                     &ray,
                     rng,
@@ -257,40 +516,32 @@ pub fn intersect_spheres(
                 };
 
                 let specular = intersect_spheres(
-                    max_bounces,
-                    max_wormhole_bounces,
+                    trace,
                     bounce_count + 1,
                     wormhole_bounce_count,
-                    &scene,
-                    depth_pass,
-                    normal_pass,
-                    &spheres,
+                    scene,
                     bounce_sphere.object_id,
                     &ray_specular,
                     rng,
                 );
 
                 let diffuse = intersect_spheres(
-                    max_bounces,
-                    max_wormhole_bounces,
+                    trace,
                     bounce_count + 1,
                     wormhole_bounce_count,
-                    &scene,
-                    depth_pass,
-                    normal_pass,
-                    &spheres,
+                    scene,
                     bounce_sphere.object_id,
                     &ray_diffuse,
                     rng,
-                ) * bounce_sphere.material.color;
+                ) * spectrum(bounce_sphere.material.color);
 
-                let emission = bounce_sphere.material.emission_color
+                let emission = spectrum(bounce_sphere.material.emission_color)
                     * bounce_sphere.material.emission_intensity;
 
-                let dielectric = specular * fresnel + diffuse * (1.0 - fresnel) + emission;
+                let dielectric = specular * fresnel + diffuse * (Col::new(1.0, 1.0, 1.0) - fresnel) + emission;
 
                 col = dielectric * (1.0 - metallic)
-                    + specular * bounce_sphere.material.color * metallic;
+                    + specular * spectrum(bounce_sphere.material.color) * metallic;
             } else {
                 let specular = specular * (1.0 - roughness) + diffuse * roughness;
                 let ray_specular = Ray {
@@ -301,18 +552,14 @@ pub fn intersect_spheres(
                 };
 
                 col = intersect_spheres(
-                    max_bounces,
-                    max_wormhole_bounces,
+                    trace,
                     bounce_count + 1,
                     wormhole_bounce_count,
-                    &scene,
-                    depth_pass,
-                    normal_pass,
-                    &spheres,
+                    scene,
                     bounce_sphere.object_id,
                     &ray_specular,
                     rng,
-                ) * bounce_sphere.material.color;
+                ) * spectrum(bounce_sphere.material.color);
             }
         }
     };
@@ -0,0 +1,232 @@
+use crate::helpers::Col;
+use std::time::{Duration, Instant};
+
+// Fewer samples than this give a noise estimate that is too unreliable to stop on
+const MIN_NOISE_SAMPLES: u32 = 8;
+// Share of pixels that must be under the noise threshold
+const NOISE_PERCENTILE: f32 = 0.99;
+
+// When a render counts as done. Whichever condition is met first stops the render,
+// and with no conditions it never stops.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StopConditions {
+    pub samples: Option<u32>,
+    pub time_budget: Option<Duration>,
+    pub noise_threshold: Option<f32>,
+}
+
+impl StopConditions {
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_none() && self.time_budget.is_none() && self.noise_threshold.is_none()
+    }
+}
+
+// Estimates per-pixel noise by comparing the full average with the average of only
+// the even-numbered samples. Both converge to the same image, so their difference
+// shrinks with the noise.
+#[derive(Debug, Clone, Default)]
+pub struct NoiseEstimator {
+    // Sum of the even-numbered samples
+    pub half_buffer: Vec<Col>,
+    // The accumulation buffer as of the last sample
+    pub previous: Vec<Col>,
+}
+
+impl NoiseEstimator {
+    // Call after every sample with the accumulation buffer and the number of samples in it
+    pub fn add_sample(&mut self, render_buffer: &[Col], samples: u32) {
+        if samples <= 1 || self.previous.len() != render_buffer.len() {
+            self.half_buffer = vec![Col::new(0.0, 0.0, 0.0); render_buffer.len()];
+            self.previous = vec![Col::new(0.0, 0.0, 0.0); render_buffer.len()];
+        }
+
+        // The sample index is samples - 1, so odd counts end on an even sample
+        let even = samples % 2 == 1;
+        for ((half, previous), col) in self
+            .half_buffer
+            .iter_mut()
+            .zip(self.previous.iter_mut())
+            .zip(render_buffer)
+        {
+            if even {
+                *half += *col - *previous;
+            }
+            *previous = *col;
+        }
+    }
+
+    // The noise level most pixels are under, relative to their brightness.
+    // Only available for an even number of samples, so both halves are equal.
+    pub fn noise(&self, samples: u32) -> Option<f32> {
+        if samples < MIN_NOISE_SAMPLES || samples % 2 == 1 || self.previous.is_empty() {
+            return None;
+        }
+
+        let full_samples = samples as f32;
+        let half_samples = (samples / 2) as f32;
+        let mut errors = self
+            .previous
+            .iter()
+            .zip(&self.half_buffer)
+            .map(|(full, half)| {
+                let full = *full / full_samples;
+                let half = *half / half_samples;
+                let diff = (full.r - half.r).abs() + (full.g - half.g).abs() + (full.b - half.b).abs();
+                diff / (full.r + full.g + full.b + 0.0001).sqrt()
+            })
+            .collect::<Vec<f32>>();
+
+        let index = ((errors.len() - 1) as f32 * NOISE_PERCENTILE) as usize;
+        let (_, error, _) = errors.select_nth_unstable_by(index, |a, b| a.total_cmp(b));
+        Some(*error)
+    }
+}
+
+// Tracks a render against its stop conditions and estimates the time left
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub conditions: StopConditions,
+    pub start: Instant,
+    pub samples: u32,
+    // Samples already in the buffer when this run started, from a checkpoint
+    pub first_sample: u32,
+    // Time spent up to the last sample
+    pub elapsed: Duration,
+    pub noise: Option<f32>,
+    // Whether to keep the even samples for the noise estimate. Copying the buffer every
+    // sample is only worth it with a noise threshold, or for a checkpoint to resume with one.
+    pub estimate_noise: bool,
+    pub estimator: NoiseEstimator,
+}
+
+impl Progress {
+    pub fn new(conditions: StopConditions) -> Progress {
+        Progress {
+            conditions,
+            start: Instant::now(),
+            samples: 0,
+            first_sample: 0,
+            elapsed: Duration::new(0, 0),
+            noise: None,
+            estimate_noise: conditions.noise_threshold.is_some(),
+            estimator: NoiseEstimator::default(),
+        }
+    }
+
+    // Continue from a buffer that already holds `samples` samples
+    pub fn resume(&mut self, render_buffer: &[Col], half_buffer: &[Col], samples: u32) {
+        self.restart();
+        self.samples = samples;
+        self.first_sample = samples;
+        self.estimator.previous = render_buffer.to_vec();
+        self.estimator.half_buffer = half_buffer.to_vec();
+        self.noise = self.estimator.noise(samples);
+    }
+
+    // Start over, for when the accumulation buffer was cleared
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        self.samples = 0;
+        self.first_sample = 0;
+        self.elapsed = Duration::new(0, 0);
+        self.noise = None;
+    }
+
+    // Call after every sample with the accumulation buffer and the number of samples in it
+    pub fn add_sample(&mut self, render_buffer: &[Col], samples: u32) {
+        self.samples = samples;
+        self.elapsed = self.start.elapsed();
+        if self.estimate_noise {
+            self.estimator.add_sample(render_buffer, samples);
+        }
+        if self.conditions.noise_threshold.is_some() {
+            if let Some(noise) = self.estimator.noise(samples) {
+                self.noise = Some(noise);
+            }
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        let StopConditions {
+            samples,
+            time_budget,
+            noise_threshold,
+        } = self.conditions;
+
+        samples.is_some_and(|samples| self.samples >= samples)
+            || time_budget.is_some_and(|budget| self.start.elapsed() >= budget)
+            || noise_threshold
+                .zip(self.noise)
+                .is_some_and(|(threshold, noise)| noise <= threshold)
+    }
+
+    // How far along the render is, from 0 to 1, going by the condition closest to done
+    pub fn fraction(&self) -> Option<f32> {
+        let StopConditions {
+            samples,
+            time_budget,
+            noise_threshold,
+        } = self.conditions;
+
+        let by_samples = samples.map(|samples| self.samples as f32 / samples as f32);
+        let by_time =
+            time_budget.map(|budget| self.start.elapsed().as_secs_f32() / budget.as_secs_f32());
+        // Noise falls with the square root of the sample count
+        let by_noise = noise_threshold
+            .zip(self.noise)
+            .map(|(threshold, noise)| (threshold / noise).powi(2));
+
+        by_samples
+            .into_iter()
+            .chain(by_time)
+            .chain(by_noise)
+            .fold(None, |max: Option<f32>, f| Some(max.map_or(f, |max| max.max(f))))
+            .map(|f| f.min(1.0))
+    }
+
+    // Estimated from the sample rate of this run, leaving out samples resumed from a checkpoint
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction()?;
+        let samples_this_run = self.samples - self.first_sample;
+        if fraction <= 0.0 || samples_this_run == 0 {
+            return None;
+        }
+        let samples_left = self.samples as f32 / fraction - self.samples as f32;
+        let seconds_per_sample = self.start.elapsed().as_secs_f32() / samples_this_run as f32;
+        Some(Duration::from_secs_f32(samples_left * seconds_per_sample))
+    }
+
+    // A one line summary, like "sample 12/64   noise 0.0412   18%   ETA 3.2s"
+    pub fn status(&self) -> String {
+        let mut status = match self.conditions.samples {
+            Some(samples) => format!("sample {}/{}", self.samples, samples),
+            None => format!("sample {}", self.samples),
+        };
+
+        if self.done() {
+            status += &format!("   done in {}", format_duration(self.elapsed));
+            return status;
+        }
+        if let Some(noise) = self.noise {
+            status += &format!("   noise {:.4}", noise);
+        }
+        if let Some(fraction) = self.fraction() {
+            status += &format!("   {:.0}%", fraction * 100.0);
+        }
+        if let Some(eta) = self.eta() {
+            status += &format!("   ETA {}", format_duration(eta));
+        }
+        status
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f32();
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else if seconds < 3600.0 {
+        format!("{}m {:02}s", seconds as u32 / 60, seconds as u32 % 60)
+    } else {
+        format!("{}h {:02}m", seconds as u32 / 3600, seconds as u32 / 60 % 60)
+    }
+}
@@ -0,0 +1,343 @@
+use crate::helpers::{clamp_max, col_to_rgb_u32, rad, Col, ObjectID};
+use crate::image::average;
+use crate::movement::{autofocus, Movement};
+use crate::pathtrace::{camera_ray, intersect_spheres, CameraSample, Trace};
+use crate::rng::PixelRng;
+use crate::scene::{Camera, Scene, Stereo};
+use crate::scene_file::{load_scene, SceneError};
+use crate::spectrum::Wavelengths;
+use rand::Rng;
+use rayon::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Beauty,
+    Depth,
+    Normal,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub max_bounces: i32,
+    pub max_wormhole_bounces: i32,
+    pub chromatic_aberration_strength: f32,
+    // Trace wavelengths instead of red, green and blue
+    pub spectral: bool,
+    pub seed: u64,
+}
+
+// Trace one sample for every pixel and add the result to the render buffer.
+// sample_iter is the index of the sample, which seeds the per-pixel random numbers.
+pub fn render_sample(
+    render_buffer: &mut [Col],
+    scene: &Scene,
+    pass: Pass,
+    sample_iter: u32,
+    movement: &Movement,
+    settings: &RenderSettings,
+) {
+    // Iterate over pixels
+    render_buffer
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, pixel)| {
+            // Update render buffer with result
+            *pixel += render_pixel(i, scene, pass, sample_iter, movement, settings);
+        });
+}
+
+// A rectangle of pixels, in display order coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    // Cover an image with tiles of at most size * size pixels, row by row
+    pub fn split(width: usize, height: usize, size: usize) -> Vec<Tile> {
+        let mut tiles = vec![];
+        for y in (0..height).step_by(size) {
+            for x in (0..width).step_by(size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    pub fn pixels(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+// Trace the samples with indices in `samples` for every pixel of a tile.
+// The buffer holds just the tile's pixels. Each pixel sums its samples in the same order
+// as render_sample would, so tiles put together give exactly the same image.
+pub fn render_tile(
+    tile_buffer: &mut [Col],
+    tile: Tile,
+    scene: &Scene,
+    pass: Pass,
+    samples: Range<u32>,
+    movement: &Movement,
+    settings: &RenderSettings,
+) {
+    tile_buffer
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(j, pixel)| {
+            let i = (tile.y + j / tile.width) * settings.width + tile.x + j % tile.width;
+            for sample_iter in samples.clone() {
+                *pixel += render_pixel(i, scene, pass, sample_iter, movement, settings);
+            }
+        });
+}
+
+// Trace a single sample of pixel i, the index in display order
+pub fn render_pixel(
+    i: usize,
+    scene: &Scene,
+    pass: Pass,
+    sample_iter: u32,
+    movement: &Movement,
+    settings: &RenderSettings,
+) -> Col {
+    let camera = scene.camera();
+    // Each eye of a stereo camera renders its part of the image as an image of its own
+    let (eye, eye_pixel, width, height) = stereo_eye(i, settings.width, settings.height, camera.stereo);
+    let width = width as f32;
+    let height = height as f32;
+
+    let mut rng = PixelRng::new(settings.seed, i, sample_iter);
+    // Each sample sees a single wavelength, for chromatic aberration and spectral rendering
+    let wavelength = rng.gen_range(-1.0..1.0);
+    let sample = CameraSample {
+        eye,
+        wavelength,
+        chromatic_aberration_strength: settings.chromatic_aberration_strength,
+    };
+
+    // Create ray from camera
+    let (ray, vignetting) = match camera_ray(eye_pixel, scene, width, height, movement, &mut rng, &sample) {
+        Some(ray) => ray,
+        // Outside the image circle of a fisheye
+        None => return Col::new(0.0, 0.0, 0.0),
+    };
+
+    // Spectral samples take their hero wavelength from the chromatic aberration's, which
+    // splits the wavelengths of the ray so that only the hero is left
+    let wavelengths = if settings.spectral && pass == Pass::Beauty {
+        Some(Wavelengths::hero((1.0 - wavelength) / 2.0, settings.chromatic_aberration_strength > 0.0))
+    } else {
+        None
+    };
+
+    // Trace ray
+    let trace = Trace {
+        max_bounces: settings.max_bounces,
+        max_wormhole_bounces: settings.max_wormhole_bounces,
+        depth_pass: pass == Pass::Depth,
+        normal_pass: pass == Pass::Normal,
+        wavelengths,
+    };
+    let col = intersect_spheres(&trace, 0, 0, scene, ObjectID::from(0), &ray, &mut rng);
+
+    let col = match wavelengths {
+        Some(wavelengths) => wavelengths.to_rgb(col),
+        // The sample only carries its own wavelength's share of the light
+        None if settings.chromatic_aberration_strength > 0.0 && pass == Pass::Beauty => {
+            col * wavelength_tint(wavelength)
+        }
+        None => col,
+    };
+
+    // Vignetting darkens the image, but not the depth and normals
+    if pass == Pass::Beauty {
+        col * vignetting
+    } else {
+        col
+    }
+}
+
+// The color of a wavelength, from red at -1 through green to blue at 1. Every channel
+// averages to 1 across the spectrum, so chromatic aberration keeps the white balance.
+fn wavelength_tint(wavelength: f32) -> Col {
+    // Red and blue each cover 3/8 of these hues, and green 3/4
+    let col = Col::from_hue((wavelength + 1.0) / 3.0);
+    Col::new(col.r / 0.375, col.g / 0.75, col.b / 0.375)
+}
+
+// The eye pixel i of a stereo image is seen with, -1 for the left, 1 for the right and 0
+// for mono, and where it is within that eye's part of the image: its index and the size
+// of the part
+fn stereo_eye(i: usize, width: usize, height: usize, stereo: Stereo) -> (f32, usize, usize, usize) {
+    let (x, y) = (i % width, i / width);
+    match stereo {
+        Stereo::Mono => (0.0, i, width, height),
+        Stereo::SideBySide => {
+            let left = width / 2;
+            if x < left {
+                (-1.0, y * left + x, left, height)
+            } else {
+                (1.0, y * (width - left) + x - left, width - left, height)
+            }
+        }
+        Stereo::TopBottom => {
+            let top = height / 2;
+            if y < top {
+                (-1.0, i, width, top)
+            } else {
+                (1.0, i - top * width, width, height - top)
+            }
+        }
+    }
+}
+
+// Average the accumulated samples and convert them to displayable pixels
+pub fn update_output_buffer(render_buffer: &[Col], output_buffer: &mut [u32], sample_iter: u32) {
+    for (col_1, col_2) in render_buffer.iter().zip(output_buffer.iter_mut()) {
+        let col = Col::new(
+            clamp_max(col_1.r / sample_iter as f32, 1.0),
+            clamp_max(col_1.g / sample_iter as f32, 1.0),
+            clamp_max(col_1.b / sample_iter as f32, 1.0),
+        );
+
+        *col_2 = col_to_rgb_u32(col);
+    }
+}
+
+// Show an anamorphic image de-squeezed in the same frame buffer, shrunk to fit with black
+// bars above and below, or to the sides for a squeeze under 1
+pub fn letterbox(output_buffer: &mut [u32], width: usize, height: usize, squeeze: f32) {
+    if squeeze == 1.0 {
+        return;
+    }
+    let picture_width = ((width as f32 * squeeze.min(1.0)).round() as usize).clamp(1, width);
+    let picture_height = ((height as f32 / squeeze.max(1.0)).round() as usize).clamp(1, height);
+    let (left, top) = ((width - picture_width) / 2, (height - picture_height) / 2);
+
+    let squeezed = output_buffer.to_vec();
+    for (i, pixel) in output_buffer.iter_mut().enumerate() {
+        let (x, y) = (i % width, i / width);
+        *pixel = if (left..left + picture_width).contains(&x) && (top..top + picture_height).contains(&y) {
+            let source_x = (x - left) * width / picture_width;
+            let source_y = (y - top) * height / picture_height;
+            squeezed[source_y * width + source_x]
+        } else {
+            0
+        };
+    }
+}
+
+// Renders a scene into caller-provided buffers, without any windowing.
+//
+//     let mut renderer = Renderer::load(Path::new("scenes/demo.scene"), settings)?;
+//     renderer.autofocus();
+//     let mut buffer = vec![Col::new(0.0, 0.0, 0.0); width * height];
+//     renderer.render(Pass::Beauty, &mut buffer, 0, 64);
+//     let image = average(&buffer, 64);
+pub struct Renderer {
+    pub scene: Scene,
+    pub settings: RenderSettings,
+    pub movement: Movement,
+}
+
+impl Renderer {
+    pub fn new(scene: Scene, settings: RenderSettings) -> Renderer {
+        let movement = Movement::new(scene.camera());
+        Renderer {
+            scene,
+            settings,
+            movement,
+        }
+    }
+
+    // Load a scene file, using the seed from the settings for random placement
+    pub fn load(path: &Path, settings: RenderSettings) -> Result<Renderer, SceneError> {
+        let scene = load_scene(path, settings.seed)?;
+        Ok(Renderer::new(scene, settings))
+    }
+
+    // The active camera
+    pub fn camera(&self) -> &Camera {
+        self.scene.camera()
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.movement = Movement::new(&camera);
+        *self.scene.camera_mut() = camera;
+    }
+
+    // Render from another of the scene's cameras
+    pub fn set_active_camera(&mut self, camera: usize) {
+        self.scene.active_camera = camera;
+        self.movement = Movement::new(self.scene.camera());
+    }
+
+    // Focus the camera on whatever is in the center of the image
+    pub fn autofocus(&mut self) {
+        let image_plane_size = 2.0 * rad(self.scene.camera().fov / 2.0).tan();
+        autofocus(
+            true,
+            self.settings.width as f32,
+            self.settings.height as f32,
+            &mut self.scene,
+            image_plane_size,
+            &self.movement,
+        );
+    }
+
+    // Add `samples` samples per pixel to the buffer, starting at sample index `first_sample`.
+    // The buffer holds width * height pixels in display order, top row first.
+    pub fn render(&self, pass: Pass, buffer: &mut [Col], first_sample: u32, samples: u32) {
+        assert_eq!(
+            buffer.len(),
+            self.settings.width * self.settings.height,
+            "the buffer must hold width * height pixels"
+        );
+
+        for sample_iter in first_sample..first_sample + samples {
+            render_sample(
+                buffer,
+                &self.scene,
+                pass,
+                sample_iter,
+                &self.movement,
+                &self.settings,
+            );
+        }
+    }
+
+    // Add `samples` samples per pixel to a buffer holding only the pixels of a tile
+    pub fn render_tile(&self, pass: Pass, tile: Tile, buffer: &mut [Col], first_sample: u32, samples: u32) {
+        assert_eq!(buffer.len(), tile.pixels(), "the buffer must hold the tile's pixels");
+
+        render_tile(
+            buffer,
+            tile,
+            &self.scene,
+            pass,
+            first_sample..first_sample + samples,
+            &self.movement,
+            &self.settings,
+        );
+    }
+
+    // Render an averaged image of a single pass, such as the depth or normal pass
+    pub fn aov(&self, pass: Pass, samples: u32) -> Vec<Col> {
+        let mut buffer = vec![Col::new(0.0, 0.0, 0.0); self.settings.width * self.settings.height];
+        self.render(pass, &mut buffer, 0, samples);
+        average(&buffer, samples)
+    }
+}
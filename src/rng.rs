@@ -0,0 +1,56 @@
+use rand::{Error, RngCore};
+
+// Random numbers for a single pixel sample.
+//
+// The generator is seeded from (seed, pixel index, sample index), so every pixel
+// sample sees the same random sequence no matter which rayon thread traces it, or
+// in which order. Together with a fixed seed this makes renders reproducible.
+#[derive(Debug, Clone)]
+pub struct PixelRng {
+    state: u64,
+}
+
+impl PixelRng {
+    pub fn new(seed: u64, pixel: usize, sample: u32) -> PixelRng {
+        let state = mix(mix(mix(seed) ^ pixel as u64) ^ sample as u64);
+        PixelRng { state }
+    }
+
+    // A sequence that depends on the seed alone, such as for scattering spheres around a
+    // scene. Unlike the generators rand provides, it stays the same between releases.
+    pub fn from_seed(seed: u64) -> PixelRng {
+        PixelRng { state: mix(seed) }
+    }
+}
+
+// SplitMix64 finalizer
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for PixelRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // SplitMix64: step the state by the golden ratio and scramble it
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -1,20 +1,140 @@
+use crate::animation::Animation;
+use crate::aperture::Aperture;
+use crate::distortion::Distortion;
 use crate::bresenham::Line3d;
+use crate::hdr::HdrImage;
 use crate::helpers::ObjectID;
 use crate::helpers::{col_to_rgb_u32, Col};
-use cgmath::Vector3;
-// use rand::prelude::*;
-use rand::{thread_rng, Rng};
+use crate::scene_file::parse_scene;
+use cgmath::{Vector2, Vector3};
+use std::path::Path;
+use std::sync::Arc;
+
+// The furthest the plane of focus can be tilted, in degrees, short of lying flat along
+// the view where it would never be reached
+pub const MAX_TILT: f32 = 80.0;
 
 #[derive(Debug, Clone)]
 pub struct Camera {
+    // Name given in the scene file, if any
+    pub name: Option<String>,
     pub pos: Vector3<f32>,
     pub rot: Vector3<f32>,
+    // Vertical field of view in degrees, or the field of view across the image height
+    // for a fisheye
     pub fov: f32,
     pub focal_length: f32,
     pub aperture_radius: f32,
+    pub aperture: Aperture,
+    // How many times wider an anamorphic lens sees than the image it makes, which is
+    // stretched back out when saved, or 1 for a spherical lens
+    pub anamorphic_squeeze: f32,
+    pub distortion: Distortion,
+    // How much the image darkens towards its edges, from 0 to 1 for the full cos^4 falloff
+    pub vignetting: f32,
+    // How far the lens barrel moves across the aperture, in aperture radii per image height
+    // from the center, which cuts out of focus highlights down to cat's eyes
+    pub optical_vignetting: f32,
+    // How far the lens is moved off the center of the image, in image heights, x to the right
+    // and y up, like a shift lens that keeps verticals parallel while looking up at a building
+    pub shift: Vector2<f32>,
+    // How far the plane of focus leans back (x) and turns to the right (y) in degrees, from
+    // facing the camera, like the Scheimpflug plane of a tilted lens
+    pub tilt: Vector2<f32>,
+    pub projection: Projection,
+    // Height of the view in scene units for the orthographic projection
+    pub ortho_height: f32,
+    pub stereo: Stereo,
+    // Distance between the left and right eye
+    pub interocular_distance: f32,
+    // Distance at which the eyes' images line up, or 0 for parallel eyes
+    pub convergence: f32,
     pub object_id: ObjectID,
 }
 
+// How the two eyes of a stereo camera share the image. The left eye is on the left,
+// or on top. An equirectangular camera in stereo renders an omni-directional stereo
+// panorama, where the eyes circle the camera position as they look around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stereo {
+    Mono,
+    SideBySide,
+    TopBottom,
+}
+
+const STEREO_NAMES: &[(&str, Stereo)] = &[
+    ("mono", Stereo::Mono),
+    ("side_by_side", Stereo::SideBySide),
+    ("top_bottom", Stereo::TopBottom),
+];
+
+impl Stereo {
+    pub fn from_name(name: &str) -> Option<Stereo> {
+        STEREO_NAMES.iter().find(|(n, _)| *n == name).map(|(_, stereo)| *stereo)
+    }
+
+    pub fn name(&self) -> &'static str {
+        STEREO_NAMES.iter().find(|(_, stereo)| stereo == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+impl Camera {
+    // Narrow the view by a factor, or widen it with a factor over 1
+    pub fn zoom(&mut self, factor: f32) {
+        match self.projection {
+            Projection::Orthographic => self.ortho_height *= factor,
+            _ => self.fov *= factor,
+        }
+    }
+
+    // Whether the plane of focus is tilted away from facing the camera
+    pub fn tilted(&self) -> bool {
+        self.tilt != Vector2::new(0.0, 0.0)
+    }
+
+    pub fn shifted(&self) -> bool {
+        self.shift != Vector2::new(0.0, 0.0)
+    }
+}
+
+// How a camera maps directions onto the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // A pinhole or thin lens
+    Perspective,
+    // Parallel rays, sized by the camera's ortho_height
+    Orthographic,
+    // Equidistant fisheye, the distance from the image center is proportional to the angle
+    Fisheye,
+    // A full 360x180 degree panorama in latitude and longitude, for a 2:1 image
+    Equirectangular,
+}
+
+const PROJECTION_NAMES: &[(&str, Projection)] = &[
+    ("perspective", Projection::Perspective),
+    ("orthographic", Projection::Orthographic),
+    ("fisheye", Projection::Fisheye),
+    ("equirectangular", Projection::Equirectangular),
+];
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Projection> {
+        PROJECTION_NAMES.iter().find(|(n, _)| *n == name).map(|(_, projection)| *projection)
+    }
+
+    pub fn name(&self) -> &'static str {
+        PROJECTION_NAMES.iter().find(|(_, projection)| projection == self).map(|(n, _)| *n).unwrap()
+    }
+
+    // The widest field of view the projection can show
+    pub fn max_fov(&self) -> f32 {
+        match self {
+            Projection::Fisheye => 360.0,
+            _ => 179.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Sphere {
     pub pos: Vector3<f32>,
@@ -36,6 +156,7 @@ pub struct Light {
 pub struct Sky {
     pub colors: Vec<Col>,
     pub intensity: f32,
+    pub environment: Option<Arc<HdrImage>>,
 }
 
 pub struct Ray {
@@ -53,7 +174,7 @@ pub struct WormholeParams {
 }
 
 impl WormholeParams {
-    fn none() -> WormholeParams {
+    pub fn none() -> WormholeParams {
         WormholeParams {
             is_wormhole: false,
             wormhole_offset: Vector3::new(0.0, 0.0, 0.0),
@@ -82,12 +203,64 @@ impl Wireframe {
         Wireframe { lines: lines }
     }
 
+    // Latitude and longitude lines around a sphere
+    pub fn globe(pos: Vector3<f32>, radius: f32, rings: usize, col: Col) -> Wireframe {
+        let mut globe = Wireframe::new(vec![]);
+        let v_iter = rings as f32;
+        let h_iter = v_iter * 2.0;
+
+        for l in 0..v_iter as usize {
+            for r in 0..h_iter as usize {
+                let t1 = l as f32;
+                let t2 = l as f32 + 1.0;
+
+                let w1 = (t1 / v_iter * std::f32::consts::PI).sin() * radius;
+                let w2 = (t2 / v_iter * std::f32::consts::PI).sin() * radius;
+
+                // Vertical line
+
+                let line1_p1 = Vector3::new(
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
+                    (t1 / v_iter * std::f32::consts::PI).cos() * radius,
+                );
+
+                let line1_p2 = Vector3::new(
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w2,
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w2,
+                    (t2 / v_iter * std::f32::consts::PI).cos() * radius,
+                );
+
+                // Horizontal line
+
+                let line2_p1 = Vector3::new(
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
+                    (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
+                    (t1 / v_iter * std::f32::consts::PI).cos() * radius,
+                );
+
+                let line2_p2 = Vector3::new(
+                    ((r + 1) as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
+                    ((r + 1) as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
+                    (t1 / v_iter * std::f32::consts::PI).cos() * radius,
+                );
+
+                let line1 = Line3d::new(line1_p1 + pos, line1_p2 + pos, col);
+                let line2 = Line3d::new(line2_p1 + pos, line2_p2 + pos, col);
+                globe.lines.push(line1);
+                globe.lines.push(line2);
+            }
+        }
+
+        globe
+    }
+
     pub fn render(
         &self,
         buffer: &mut Vec<u32>,
         camera: &Camera,
-        display_width: &'static usize,
-        display_height: &'static usize,
+        display_width: usize,
+        display_height: usize,
     ) {
         &self.lines.iter().for_each(|line| {
             for (x, y) in line.render_line(camera, display_width, display_height) {
@@ -102,301 +275,53 @@ impl Wireframe {
 #[derive(Debug, Clone)]
 pub struct Scene {
     pub cameras: Vec<Camera>,
+    // Index of the camera the scene is rendered from
+    pub active_camera: usize,
     pub spheres: Vec<Sphere>,
     pub sky: Sky,
     pub wireframes: Vec<Wireframe>,
+    pub animation: Animation,
 }
 
-pub fn initialize_scene() -> Scene {
-    let mut rng = thread_rng();
-
-    let mut object_id = ObjectID::from(0);
-
-    let mut scene = Scene {
-        cameras: vec![Camera {
-            pos: Vector3::new(10.0, -10.0, 10.0),
-            rot: Vector3::new(0.0, 0.0, 0.0),
-            fov: 90.0,
-            focal_length: 8.0,
-            aperture_radius: 0.015,
-            object_id: object_id.next(),
-        }],
-        spheres: vec![],
-        sky: Sky {
-            colors: vec![Col::new(0.3, 0.6, 0.9), Col::new(0.9, 0.9, 0.9)],
-            intensity: 1.0,
-        },
-        wireframes: vec![],
-    };
-
-    let wormhole_pos = Vector3::new(0.0, 0.0, 8.0);
-    let wormhole_offset = Vector3::new(-2.0, 12.0, -6.0);
-
-    // Spheres
-
-    let mut spheres = vec![
-        // Wireframe sphere
-        Sphere {
-            pos: Vector3::new(10.0, 2.0, 1.0),
-            radius: 1.0,
-            material: Material {
-                color: Col::new(0.1, 0.1, 0.1),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        },
-        // Origin
-        Sphere {
-            pos: Vector3::new(2.0, 0.0, 0.0),
-            radius: 0.3,
-            material: Material {
-                color: Col::new(1.0, 0.0, 0.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        },
-        Sphere {
-            pos: Vector3::new(0.0, 2.0, 0.0),
-            radius: 0.3,
-            material: Material {
-                color: Col::new(0.0, 1.0, 0.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        },
-        Sphere {
-            pos: Vector3::new(0.0, 0.0, 2.0),
-            radius: 0.3,
-            material: Material {
-                color: Col::new(0.1, 0.3, 1.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        },
-        // Light
-        Sphere {
-            pos: Vector3::new(-6.0, 0.0, 2.0),
-            radius: 3.0,
-            material: Material {
-                color: Col::new(0.0, 0.0, 0.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                // emission_color: Col::new(4.0, 2.0, 1.0),
-                emission_intensity: 1.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        },
-        // Wormhole pt. 1
-        Sphere {
-            pos: wormhole_pos,
-            radius: 2.0,
-            material: Material {
-                color: Col::new(0.0, 0.0, 0.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 1.0,
-                wormhole_params: WormholeParams {
-                    is_wormhole: true,
-                    wormhole_offset: wormhole_offset,
-                    other_end_object_id: object_id.next() + 1,
-                },
-            },
-            object_id: object_id,
-        },
-        // Wormhole pt. 2
-        Sphere {
-            pos: wormhole_pos + wormhole_offset,
-            radius: 2.0,
-            material: Material {
-                color: Col::new(0.0, 0.0, 0.0),
-                metallic: 0.0,
-                roughness: 1.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 1.0,
-                wormhole_params: WormholeParams {
-                    is_wormhole: true,
-                    wormhole_offset: wormhole_offset * -1.0,
-                    other_end_object_id: object_id,
-                },
-            },
-            object_id: object_id.next(),
-        },
-    ];
-    for i in 0..6 {
-        spheres.push(Sphere {
-            pos: Vector3::new(-7.5 + 2.5 * i as f32, 8.0, 1.0),
-            radius: 1.0,
-            material: Material {
-                color: Col::new(1.0, 1.0, 1.0),
-                metallic: 1.0,
-                roughness: (i as f32 / 6.0).powi(2),
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        });
+impl Scene {
+    pub fn camera(&self) -> &Camera {
+        &self.cameras[self.active_camera]
     }
 
-    for i in 0..6 {
-        spheres.push(Sphere {
-            pos: Vector3::new(-7.5 + 2.5 * i as f32, 8.0, -2.0),
-            radius: 1.0,
-            material: Material {
-                color: Col::new(1.0, 1.0, 1.0),
-                metallic: 1.0,
-                roughness: 0.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        });
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.cameras[self.active_camera]
     }
 
-    for _ in 0..50 {
-        let rnd = rng.gen_range(0.0..1.0);
-
-        spheres.push(Sphere {
-            pos: Vector3::new(
-                rng.gen_range(-5.0..5.0),
-                rng.gen_range(-5.0..5.0),
-                rng.gen_range(-5.0..5.0),
-            ),
-            radius: rng.gen_range(0.5..1.0),
-            material: Material {
-                color: Col::new(
-                    rng.gen_range(0.0..1.0),
-                    rng.gen_range(0.0..1.0),
-                    rng.gen_range(0.0..1.0),
-                ),
-                metallic: if rnd < 0.5 { 0.0 } else { 1.0 },
-                roughness: 0.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: 0.0,
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        });
+    // Look up a camera by its name in the scene file or by its index
+    pub fn find_camera(&self, name: &str) -> Option<usize> {
+        self.cameras
+            .iter()
+            .position(|camera| camera.name.as_deref() == Some(name))
+            .or_else(|| name.parse().ok().filter(|i| *i < self.cameras.len()))
     }
 
-    for _ in 0..50 {
-
-        spheres.push(Sphere {
-            pos: Vector3::new(
-                rng.gen_range(15.0..25.0),
-                rng.gen_range(-5.0..5.0),
-                rng.gen_range(-5.0..5.0),
-            ),
-            radius: 0.1,
-            material: Material {
-                color: Col::new(1.0, 1.0, 1.0),
-                metallic: 0.0,
-                roughness: 0.0,
-                emission_color: Col::new(1.0, 1.0, 1.0),
-                emission_intensity: rng.gen_range(1.0..2.5),
-                wormhole_params: WormholeParams::none(),
-            },
-            object_id: object_id.next(),
-        });
+    // The camera's name, or its index if it has none
+    pub fn camera_label(&self, i: usize) -> String {
+        self.cameras[i].name.clone().unwrap_or_else(|| i.to_string())
     }
-    scene.spheres = spheres;
-
-    // Wireframes
-
-    let mut wireframes = vec![];
-
-    let origin = Wireframe::new(vec![
-        Line3d::new(
-            Vector3::new(0.0, 0.0, 0.0),
-            Vector3::new(2.0, 0.0, 0.0),
-            Col::new(1.0, 0.0, 0.0),
-        ),
-        Line3d::new(
-            Vector3::new(0.0, 0.0, 0.0),
-            Vector3::new(0.0, 2.0, 0.0),
-            Col::new(0.0, 1.0, 0.0),
-        ),
-        Line3d::new(
-            Vector3::new(0.0, 0.0, 0.0),
-            Vector3::new(0.0, 0.0, 2.0),
-            Col::new(0.1, 0.3, 1.0),
-        ),
-    ]);
-
-    let mut sphere_test: Wireframe = Wireframe::new(vec![]);
-    let v_iter = 8.0;
-    let h_iter = v_iter * 2.0;
-
-    for l in 0..v_iter as usize {
-        for r in 0..h_iter as usize {
-            let radius = 1.0;
-            let col = Col::new(0.3, 0.3, 0.3);
-            let t1 = l as f32;
-            let t2 = l as f32 + 1.0;
-
-            let w1 = (t1 as f32 / v_iter * std::f32::consts::PI).sin() * radius;
-            let w2 = (t2 as f32 / v_iter * std::f32::consts::PI).sin() * radius;
-
-            // Vertical line
-
-            let line1_p1 = Vector3::new(
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
-                (t1 as f32 / v_iter * std::f32::consts::PI).cos() * radius,
-            );
-
-            let line1_p2 = Vector3::new(
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w2,
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w2,
-                (t2 as f32 / v_iter * std::f32::consts::PI).cos() * radius,
-            );
-
-            // Horizontal line
-
-            let line2_p1 = Vector3::new(
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
-                (r as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
-                (t1 as f32 / v_iter * std::f32::consts::PI).cos() * radius,
-            );
-
-            let line2_p2 = Vector3::new(
-                ((r + 1) as f32 / h_iter * 2.0 * std::f32::consts::PI).sin() * w1,
-                ((r + 1) as f32 / h_iter * 2.0 * std::f32::consts::PI).cos() * w1,
-                (t1 as f32 / v_iter * std::f32::consts::PI).cos() * radius,
-            );
-
-            let pos = scene.spheres[0].pos;
-            let line1 = Line3d::new(line1_p1 + pos, line1_p2 + pos, col);
-            let line2 = Line3d::new(line2_p1 + pos, line2_p2 + pos, col);
-            sphere_test.lines.push(line1);
-            sphere_test.lines.push(line2);
-        }
-    }
-
-    wireframes.push(origin);
-    wireframes.push(sphere_test);
 
-    scene.wireframes = wireframes;
+    // Add a copy of the active camera and make it the active one
+    pub fn add_camera(&mut self) -> usize {
+        let ids = self.cameras.iter().map(|camera| camera.object_id);
+        let ids = ids.chain(self.spheres.iter().map(|sphere| sphere.object_id));
+        let last_id = ids.map(i32::from).max().unwrap_or(0);
+
+        let mut camera = self.camera().clone();
+        camera.name = None;
+        camera.object_id = ObjectID::from(last_id + 1);
+        self.cameras.push(camera);
+        self.active_camera = self.cameras.len() - 1;
+        self.active_camera
+    }
+}
 
-    return scene;
+// The built-in demo scene
+pub fn initialize_scene(seed: u64) -> Scene {
+    parse_scene(include_str!("../scenes/demo.scene"), Path::new("scenes"), seed)
+        .unwrap_or_else(|e| panic!("scenes/demo.scene:{}", e))
 }
@@ -0,0 +1,1055 @@
+use crate::animation::{Animation, CameraAnimation, Interpolation, SphereAnimation, Track};
+use crate::aperture::{Aperture, ApertureMask};
+use crate::distortion::Distortion;
+use crate::bresenham::Line3d;
+use crate::hdr::read_hdr;
+use crate::helpers::{Col, ObjectID};
+use crate::rng::PixelRng;
+use crate::scene::{Camera, Material, Projection, Scene, Sky, Sphere, Stereo, Wireframe, WormholeParams, MAX_TILT};
+use cgmath::{Vector2, Vector3};
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Scene description files.
+//
+// A scene file is a list of statements, one per line. A statement is a key followed
+// by its arguments and optionally a block of nested statements in braces:
+//
+//     # Comments run to the end of the line
+//     camera main {
+//         pos 10 -10 10
+//         fov 90
+//     }
+//     sphere light {
+//         pos -6 0 2
+//         radius 3
+//         material {
+//             emission_intensity 1
+//         }
+//     }
+//     wormhole portal_a portal_b
+//     animate camera main {
+//         key 0 {
+//             pos 10 -10 10
+//         }
+//         key 2.5 {
+//             pos 0 -20 5
+//         }
+//     }
+//
+// See scenes/demo.scene for every supported statement.
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, ParseError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Parse(path, e) => write!(f, "{}:{}", path.display(), e),
+        }
+    }
+}
+
+pub fn load_scene(path: &Path, seed: u64) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    parse_scene(&source, base_dir, seed).map_err(|e| SceneError::Parse(path.to_path_buf(), e))
+}
+
+// Parse a scene from source text. Relative file names are resolved against base_dir,
+// and the seed drives the random placement of scattered spheres.
+pub fn parse_scene(source: &str, base_dir: &Path, seed: u64) -> Result<Scene, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut pos = 0;
+    let nodes = parse_block(&tokens, &mut pos, None)?;
+
+    SceneBuilder::new(base_dir, seed).build(&nodes)
+}
+
+// Tokens
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    OpenBrace,
+    CloseBrace,
+    Newline,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn error<T>(line: usize, column: usize, message: String) -> Result<T, ParseError> {
+    Err(ParseError {
+        line,
+        column,
+        message,
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut push = |kind| {
+            tokens.push(Token {
+                kind,
+                line: start_line,
+                column: start_column,
+            })
+        };
+
+        match c {
+            '\n' => {
+                chars.next();
+                push(TokenKind::Newline);
+                line += 1;
+                column = 1;
+            }
+            '#' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '{' | '}' => {
+                chars.next();
+                column += 1;
+                push(if c == '{' {
+                    TokenKind::OpenBrace
+                } else {
+                    TokenKind::CloseBrace
+                });
+            }
+            '"' => {
+                chars.next();
+                column += 1;
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            column += 1;
+                            break;
+                        }
+                        Some('\n') | None => {
+                            return error(start_line, start_column, "unterminated string".into())
+                        }
+                        Some(c) => {
+                            column += 1;
+                            string.push(c);
+                        }
+                    }
+                }
+                push(TokenKind::Str(string));
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                column += 1;
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '"' || c == '#' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                    column += 1;
+                }
+                push(TokenKind::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Statements
+
+#[derive(Debug, Clone)]
+struct Arg {
+    value: String,
+    quoted: bool,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    key: String,
+    args: Vec<Arg>,
+    children: Option<Vec<Node>>,
+    line: usize,
+    column: usize,
+}
+
+// Parse statements until the end of the file, or until the closing brace of the block
+// opened by `open`
+fn parse_block(
+    tokens: &[Token],
+    pos: &mut usize,
+    open: Option<&Token>,
+) -> Result<Vec<Node>, ParseError> {
+    let mut nodes = vec![];
+
+    loop {
+        while *pos < tokens.len() && tokens[*pos].kind == TokenKind::Newline {
+            *pos += 1;
+        }
+
+        let token = match tokens.get(*pos) {
+            Some(token) => token,
+            None => {
+                return match open {
+                    Some(open) => error(open.line, open.column, "unclosed '{'".into()),
+                    None => Ok(nodes),
+                }
+            }
+        };
+
+        let key = match &token.kind {
+            TokenKind::CloseBrace if open.is_some() => {
+                *pos += 1;
+                return Ok(nodes);
+            }
+            TokenKind::Word(word) => word.clone(),
+            TokenKind::CloseBrace => {
+                return error(token.line, token.column, "unexpected '}'".into())
+            }
+            TokenKind::OpenBrace => {
+                return error(token.line, token.column, "unexpected '{'".into())
+            }
+            TokenKind::Str(_) => {
+                return error(
+                    token.line,
+                    token.column,
+                    "expected a statement, found a string".into(),
+                )
+            }
+            TokenKind::Newline => unreachable!(),
+        };
+        *pos += 1;
+
+        let mut node = Node {
+            key,
+            args: vec![],
+            children: None,
+            line: token.line,
+            column: token.column,
+        };
+
+        while let Some(token) = tokens.get(*pos) {
+            let (value, quoted) = match &token.kind {
+                TokenKind::Word(word) => (word.clone(), false),
+                TokenKind::Str(string) => (string.clone(), true),
+                _ => break,
+            };
+            node.args.push(Arg {
+                value,
+                quoted,
+                line: token.line,
+                column: token.column,
+            });
+            *pos += 1;
+        }
+
+        if let Some(token) = tokens.get(*pos) {
+            if token.kind == TokenKind::OpenBrace {
+                *pos += 1;
+                node.children = Some(parse_block(tokens, pos, Some(token))?);
+
+                // A block must be the last thing on its line
+                if let Some(next) = tokens.get(*pos) {
+                    if next.kind != TokenKind::Newline && next.kind != TokenKind::CloseBrace {
+                        return error(
+                            next.line,
+                            next.column,
+                            "expected a new line after '}'".into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        nodes.push(node);
+    }
+}
+
+impl Node {
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        error(self.line, self.column, message)
+    }
+
+    fn children(&self) -> Result<&[Node], ParseError> {
+        match &self.children {
+            Some(children) => Ok(children),
+            None => self.error(format!("'{}' needs a {{ ... }} block", self.key)),
+        }
+    }
+
+    fn no_children(&self) -> Result<(), ParseError> {
+        match &self.children {
+            Some(_) => self.error(format!("'{}' does not take a block", self.key)),
+            None => Ok(()),
+        }
+    }
+
+    fn arg_count(&self, counts: &[usize]) -> Result<(), ParseError> {
+        if counts.contains(&self.args.len()) {
+            Ok(())
+        } else {
+            let expected: Vec<String> = counts.iter().map(|c| c.to_string()).collect();
+            self.error(format!(
+                "'{}' takes {} argument(s), found {}",
+                self.key,
+                expected.join(" or "),
+                self.args.len()
+            ))
+        }
+    }
+
+    fn name(&self) -> Result<Option<String>, ParseError> {
+        match &self.args[..] {
+            [] => Ok(None),
+            [name] => Ok(Some(name.value.clone())),
+            [_, extra, ..] => error(
+                extra.line,
+                extra.column,
+                format!("unexpected argument '{}'", extra.value),
+            ),
+        }
+    }
+
+    fn floats(&self, count: usize) -> Result<Vec<f32>, ParseError> {
+        self.no_children()?;
+        self.arg_count(&[count])?;
+        self.args.iter().map(|arg| arg.float()).collect()
+    }
+
+    fn float(&self) -> Result<f32, ParseError> {
+        Ok(self.floats(1)?[0])
+    }
+
+    fn vector(&self) -> Result<Vector3<f32>, ParseError> {
+        let v = self.floats(3)?;
+        Ok(Vector3::new(v[0], v[1], v[2]))
+    }
+
+    fn color(&self) -> Result<Col, ParseError> {
+        let v = self.floats(3)?;
+        Ok(Col::new(v[0], v[1], v[2]))
+    }
+
+    // One value, or a range given as two values
+    fn range(&self) -> Result<(f32, f32), ParseError> {
+        self.no_children()?;
+        self.arg_count(&[1, 2])?;
+        let min = self.args[0].float()?;
+        let max = match self.args.get(1) {
+            Some(arg) => arg.float()?,
+            None => min,
+        };
+        if max < min {
+            return self.error(format!("'{}' range is empty", self.key));
+        }
+        Ok((min, max))
+    }
+
+    fn count(&self) -> Result<usize, ParseError> {
+        self.no_children()?;
+        self.arg_count(&[1])?;
+        let arg = &self.args[0];
+        arg.value.parse().or_else(|_| {
+            error(
+                arg.line,
+                arg.column,
+                format!("expected a whole number, found '{}'", arg.value),
+            )
+        })
+    }
+
+    fn string(&self) -> Result<String, ParseError> {
+        self.no_children()?;
+        self.arg_count(&[1])?;
+        Ok(self.args[0].value.clone())
+    }
+
+    fn unknown<T>(&self, context: &str) -> Result<T, ParseError> {
+        self.error(format!("unknown {} property '{}'", context, self.key))
+    }
+}
+
+impl Arg {
+    fn float(&self) -> Result<f32, ParseError> {
+        match self.value.parse::<f32>() {
+            Ok(value) if !self.quoted && value.is_finite() => Ok(value),
+            _ => error(
+                self.line,
+                self.column,
+                format!("expected a number, found '{}'", self.value),
+            ),
+        }
+    }
+}
+
+// Scene construction
+
+struct SceneBuilder<'a> {
+    base_dir: &'a Path,
+    rng: PixelRng,
+    scene: Scene,
+    object_id: ObjectID,
+    materials: HashMap<String, Material>,
+    sphere_names: HashMap<String, usize>,
+    camera_names: HashMap<String, usize>,
+    wormholes: Vec<&'a Node>,
+    animations: Vec<&'a Node>,
+}
+
+fn default_material() -> Material {
+    Material {
+        color: Col::new(1.0, 1.0, 1.0),
+        metallic: 0.0,
+        roughness: 1.0,
+        emission_color: Col::new(1.0, 1.0, 1.0),
+        emission_intensity: 0.0,
+        wormhole_params: WormholeParams::none(),
+    }
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn new(base_dir: &'a Path, seed: u64) -> SceneBuilder<'a> {
+        SceneBuilder {
+            base_dir,
+            rng: PixelRng::from_seed(seed),
+            scene: Scene {
+                cameras: vec![],
+                active_camera: 0,
+                spheres: vec![],
+                sky: Sky {
+                    colors: vec![Col::new(0.3, 0.6, 0.9), Col::new(0.9, 0.9, 0.9)],
+                    intensity: 1.0,
+                    environment: None,
+                },
+                wireframes: vec![],
+                animation: Animation::default(),
+            },
+            object_id: ObjectID::from(0),
+            materials: HashMap::new(),
+            sphere_names: HashMap::new(),
+            camera_names: HashMap::new(),
+            wormholes: vec![],
+            animations: vec![],
+        }
+    }
+
+    fn build(mut self, nodes: &'a [Node]) -> Result<Scene, ParseError> {
+        for node in nodes {
+            match &node.key[..] {
+                "camera" => self.camera(node)?,
+                "sky" => self.sky(node)?,
+                "material" => {
+                    let name = match node.name()? {
+                        Some(name) => name,
+                        None => return node.error("a top level material needs a name".into()),
+                    };
+                    let material = self.material(node)?;
+                    self.materials.insert(name, material);
+                }
+                "sphere" => self.sphere(node)?,
+                "scatter" => self.scatter(node)?,
+                "wormhole" => {
+                    node.no_children()?;
+                    node.arg_count(&[2])?;
+                    self.wormholes.push(node);
+                }
+                "wireframe" => self.wireframe(node)?,
+                "animate" => self.animations.push(node),
+                _ => return node.error(format!("unknown statement '{}'", node.key)),
+            }
+        }
+
+        // Wormholes are linked last, so they can refer to spheres defined anywhere
+        for node in std::mem::take(&mut self.wormholes) {
+            self.wormhole(node)?;
+        }
+
+        if self.scene.cameras.is_empty() {
+            return error(1, 1, "the scene needs at least one camera".into());
+        }
+
+        // Animations too, after all cameras and spheres are known
+        for node in std::mem::take(&mut self.animations) {
+            self.animate(node)?;
+        }
+
+        Ok(self.scene)
+    }
+
+    fn camera(&mut self, node: &Node) -> Result<(), ParseError> {
+        let name = node.name()?;
+        if let Some(name) = &name {
+            if self.camera_names.contains_key(name) {
+                return node.error(format!("a camera named '{}' already exists", name));
+            }
+            self.camera_names.insert(name.clone(), self.scene.cameras.len());
+        }
+        let mut camera = Camera {
+            name,
+            pos: Vector3::new(0.0, 0.0, 0.0),
+            rot: Vector3::new(0.0, 0.0, 0.0),
+            fov: 90.0,
+            focal_length: 8.0,
+            aperture_radius: 0.0,
+            aperture: Aperture::Circle,
+            anamorphic_squeeze: 1.0,
+            distortion: Distortion::default(),
+            vignetting: 0.0,
+            optical_vignetting: 0.0,
+            shift: Vector2::new(0.0, 0.0),
+            tilt: Vector2::new(0.0, 0.0),
+            projection: Projection::Perspective,
+            ortho_height: 10.0,
+            stereo: Stereo::Mono,
+            interocular_distance: 0.065,
+            convergence: 0.0,
+            object_id: self.object_id.next(),
+        };
+        let (mut blades, mut rotation, mut curvature, mut mask) = (None, 0.0, 0.0, None);
+
+        for child in node.children()? {
+            match &child.key[..] {
+                "pos" => camera.pos = child.vector()?,
+                "rot" => camera.rot = child.vector()?,
+                "fov" => camera.fov = child.float()?,
+                "focal_length" => camera.focal_length = child.float()?,
+                "aperture_radius" => camera.aperture_radius = child.float()?,
+                "aperture_blades" => {
+                    let count = child.float()?;
+                    if count < 3.0 || count.fract() != 0.0 {
+                        return child.error("aperture_blades needs a whole number of at least 3".into());
+                    }
+                    blades = Some(count as u32);
+                }
+                "aperture_rotation" => rotation = child.float()?,
+                "aperture_curvature" => {
+                    curvature = child.float()?;
+                    if !(0.0..=1.0).contains(&curvature) {
+                        return child.error("aperture_curvature must be from 0 to 1".into());
+                    }
+                }
+                "aperture_mask" => {
+                    let path = self.base_dir.join(child.string()?);
+                    let image = ApertureMask::load(&path).or_else(|e| {
+                        child.error(format!("could not load '{}': {}", path.display(), e))
+                    })?;
+                    mask = Some(Arc::new(image));
+                }
+                "anamorphic_squeeze" => {
+                    camera.anamorphic_squeeze = child.float()?;
+                    if camera.anamorphic_squeeze <= 0.0 {
+                        return child.error("anamorphic_squeeze must be more than 0".into());
+                    }
+                }
+                "distortion" => {
+                    child.arg_count(&[1, 2, 3])?;
+                    let k = child.floats(child.args.len())?;
+                    camera.distortion.k1 = k[0];
+                    camera.distortion.k2 = k.get(1).copied().unwrap_or(0.0);
+                    camera.distortion.k3 = k.get(2).copied().unwrap_or(0.0);
+                }
+                "tangential_distortion" => {
+                    let p = child.floats(2)?;
+                    camera.distortion.p1 = p[0];
+                    camera.distortion.p2 = p[1];
+                }
+                "vignetting" => {
+                    camera.vignetting = child.float()?;
+                    if !(0.0..=1.0).contains(&camera.vignetting) {
+                        return child.error("vignetting must be from 0 to 1".into());
+                    }
+                }
+                "optical_vignetting" => {
+                    camera.optical_vignetting = child.float()?;
+                    if camera.optical_vignetting < 0.0 {
+                        return child.error("optical_vignetting can't be negative".into());
+                    }
+                }
+                "shift" => {
+                    let shift = child.floats(2)?;
+                    camera.shift = Vector2::new(shift[0], shift[1]);
+                }
+                "tilt" => {
+                    let tilt = child.floats(2)?;
+                    if tilt.iter().any(|angle| angle.abs() >= MAX_TILT) {
+                        return child.error(format!("tilt must be less than {} degrees", MAX_TILT));
+                    }
+                    camera.tilt = Vector2::new(tilt[0], tilt[1]);
+                }
+                "projection" => {
+                    let name = child.string()?;
+                    camera.projection = match Projection::from_name(&name) {
+                        Some(projection) => projection,
+                        None => {
+                            return child.error(format!(
+                                "unknown projection '{}', expected perspective, orthographic, fisheye or equirectangular",
+                                name
+                            ))
+                        }
+                    }
+                }
+                "ortho_height" => camera.ortho_height = child.float()?,
+                "stereo" => {
+                    let name = child.string()?;
+                    camera.stereo = match Stereo::from_name(&name) {
+                        Some(stereo) => stereo,
+                        None => {
+                            return child.error(format!(
+                                "unknown stereo layout '{}', expected mono, side_by_side or top_bottom",
+                                name
+                            ))
+                        }
+                    }
+                }
+                "interocular_distance" => camera.interocular_distance = child.float()?,
+                "convergence" => camera.convergence = child.float()?,
+                _ => return child.unknown("camera"),
+            }
+        }
+
+        camera.aperture = match (blades, mask) {
+            (Some(_), Some(_)) => return node.error("a camera can't have both aperture_blades and aperture_mask".into()),
+            (Some(count), None) => Aperture::Blades {
+                count,
+                rotation,
+                curvature,
+            },
+            (None, Some(mask)) => Aperture::Mask(mask),
+            (None, None) => Aperture::Circle,
+        };
+
+        self.scene.cameras.push(camera);
+        Ok(())
+    }
+
+    fn sky(&mut self, node: &Node) -> Result<(), ParseError> {
+        node.arg_count(&[0])?;
+        let mut colors = vec![];
+
+        for child in node.children()? {
+            match &child.key[..] {
+                "color" => colors.push(child.color()?),
+                "intensity" => self.scene.sky.intensity = child.float()?,
+                "environment" => {
+                    let path = self.base_dir.join(child.string()?);
+                    let image = read_hdr(&path).or_else(|e| {
+                        child.error(format!("could not load '{}': {}", path.display(), e))
+                    })?;
+                    self.scene.sky.environment = Some(Arc::new(image));
+                }
+                _ => return child.unknown("sky"),
+            }
+        }
+
+        match colors.len() {
+            0 => (),
+            2 => self.scene.sky.colors = colors,
+            _ => return node.error("sky needs exactly two colors".into()),
+        }
+        Ok(())
+    }
+
+    fn material(&self, node: &Node) -> Result<Material, ParseError> {
+        // `material name` refers to a material defined at the top level
+        if node.children.is_none() {
+            let name = node.string()?;
+            return match self.materials.get(&name) {
+                Some(material) => Ok(material.clone()),
+                None => node.error(format!("unknown material '{}'", name)),
+            };
+        }
+
+        let mut material = default_material();
+        for child in node.children()? {
+            match &child.key[..] {
+                "color" => material.color = child.color()?,
+                "metallic" => material.metallic = child.float()?,
+                "roughness" => material.roughness = child.float()?,
+                "emission_color" => material.emission_color = child.color()?,
+                "emission_intensity" => material.emission_intensity = child.float()?,
+                _ => return child.unknown("material"),
+            }
+        }
+        Ok(material)
+    }
+
+    fn sphere(&mut self, node: &Node) -> Result<(), ParseError> {
+        let name = node.name()?;
+        let mut sphere = Sphere {
+            pos: Vector3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: default_material(),
+            object_id: self.object_id.next(),
+        };
+
+        for child in node.children()? {
+            match &child.key[..] {
+                "pos" => sphere.pos = child.vector()?,
+                "radius" => sphere.radius = child.float()?,
+                "material" => sphere.material = self.material(child)?,
+                _ => return child.unknown("sphere"),
+            }
+        }
+
+        if let Some(name) = name {
+            if self.sphere_names.contains_key(&name) {
+                return node.error(format!("a sphere named '{}' already exists", name));
+            }
+            self.sphere_names.insert(name, self.scene.spheres.len());
+        }
+        self.scene.spheres.push(sphere);
+        Ok(())
+    }
+
+    // A number of randomly placed spheres inside a box
+    fn scatter(&mut self, node: &Node) -> Result<(), ParseError> {
+        node.name()?;
+
+        let mut count = 0;
+        let mut min = Vector3::new(0.0, 0.0, 0.0);
+        let mut max = Vector3::new(0.0, 0.0, 0.0);
+        let mut radius = (1.0, 1.0);
+        let mut material = default_material();
+        let mut random_color = false;
+        let mut metallic_chance = None;
+        let mut emission_intensity = None;
+
+        for child in node.children()? {
+            match &child.key[..] {
+                "count" => count = child.count()?,
+                "min" => min = child.vector()?,
+                "max" => max = child.vector()?,
+                "radius" => radius = child.range()?,
+                "material" => material = self.material(child)?,
+                "random_color" => {
+                    child.no_children()?;
+                    child.arg_count(&[0])?;
+                    random_color = true;
+                }
+                "metallic_chance" => metallic_chance = Some(child.float()?),
+                "emission_intensity" => emission_intensity = Some(child.range()?),
+                _ => return child.unknown("scatter"),
+            }
+        }
+
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return node.error("scatter min must not be larger than max".into());
+        }
+
+        let rng = &mut self.rng;
+        let mut gen = |(min, max): (f32, f32)| {
+            if max > min {
+                rng.gen_range(min..max)
+            } else {
+                min
+            }
+        };
+
+        for _ in 0..count {
+            let mut material = material.clone();
+            if let Some(chance) = metallic_chance {
+                material.metallic = if gen((0.0, 1.0)) < chance { 1.0 } else { 0.0 };
+            }
+            let pos = Vector3::new(
+                gen((min.x, max.x)),
+                gen((min.y, max.y)),
+                gen((min.z, max.z)),
+            );
+            let radius = gen(radius);
+            if random_color {
+                material.color = Col::new(gen((0.0, 1.0)), gen((0.0, 1.0)), gen((0.0, 1.0)));
+            }
+            if let Some(range) = emission_intensity {
+                material.emission_intensity = gen(range);
+            }
+
+            self.scene.spheres.push(Sphere {
+                pos,
+                radius,
+                material,
+                object_id: self.object_id.next(),
+            });
+        }
+        Ok(())
+    }
+
+    fn wormhole(&mut self, node: &Node) -> Result<(), ParseError> {
+        let mut ends = vec![];
+        for arg in &node.args {
+            match self.sphere_names.get(&arg.value) {
+                Some(i) => ends.push(*i),
+                None => {
+                    return error(
+                        arg.line,
+                        arg.column,
+                        format!("unknown sphere '{}'", arg.value),
+                    )
+                }
+            }
+        }
+
+        let (a, b) = (ends[0], ends[1]);
+        if a == b {
+            return node.error("a wormhole needs two different spheres".into());
+        }
+        for (from, to, arg) in [(a, b, &node.args[0]), (b, a, &node.args[1])].iter() {
+            if self.scene.spheres[*from]
+                .material
+                .wormhole_params
+                .is_wormhole
+            {
+                return error(
+                    arg.line,
+                    arg.column,
+                    format!("'{}' is already part of a wormhole", arg.value),
+                );
+            }
+            let other = &self.scene.spheres[*to];
+            let params = WormholeParams {
+                is_wormhole: true,
+                wormhole_offset: other.pos - self.scene.spheres[*from].pos,
+                other_end_object_id: other.object_id,
+            };
+            self.scene.spheres[*from].material.wormhole_params = params;
+        }
+        Ok(())
+    }
+
+    // Keyframes for a camera, or the position of a sphere:
+    //
+    //     animate camera [name] {
+    //         interpolation linear|smooth
+    //         key <seconds> {
+    //             pos / rot / fov / focal_length / aperture_radius
+    //         }
+    //     }
+    //     animate sphere <name> { key <seconds> { pos } }
+    fn animate(&mut self, node: &Node) -> Result<(), ParseError> {
+        let (kind, name) = match &node.args[..] {
+            [] => return node.error("'animate' needs 'camera' or 'sphere'".into()),
+            [kind] => (kind, None),
+            [kind, name] => (kind, Some(name)),
+            [_, _, extra, ..] => {
+                return error(
+                    extra.line,
+                    extra.column,
+                    format!("unexpected argument '{}'", extra.value),
+                )
+            }
+        };
+
+        let mut interpolation = Interpolation::Smooth;
+        let mut keys: Vec<(f32, &Node)> = vec![];
+        for child in node.children()? {
+            match &child.key[..] {
+                "interpolation" => {
+                    interpolation = match &child.string()?[..] {
+                        "linear" => Interpolation::Linear,
+                        "smooth" => Interpolation::Smooth,
+                        other => {
+                            return child.error(format!(
+                                "unknown interpolation '{}', expected linear or smooth",
+                                other
+                            ))
+                        }
+                    }
+                }
+                "key" => {
+                    child.arg_count(&[1])?;
+                    let time = child.args[0].float()?;
+                    if keys.iter().any(|(t, _)| *t == time) {
+                        return child.error(format!("there already is a key at {}", time));
+                    }
+                    child.children()?;
+                    keys.push((time, child));
+                }
+                _ => return child.unknown("animate"),
+            }
+        }
+        if keys.is_empty() {
+            return node.error("an animation needs at least one key".into());
+        }
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let properties: &[&str] = match &kind.value[..] {
+            "camera" => &["pos", "rot", "fov", "focal_length", "aperture_radius"],
+            "sphere" => &["pos"],
+            _ => return error(kind.line, kind.column, format!("can't animate '{}'", kind.value)),
+        };
+        for (_, key) in &keys {
+            for property in key.children()? {
+                if !properties.contains(&&property.key[..]) {
+                    return property.unknown(&format!("animated {}", kind.value));
+                }
+            }
+        }
+
+        let vectors = |property| track(&keys, property, interpolation, Node::vector);
+        let floats = |property| track(&keys, property, interpolation, Node::float);
+        let animation = &mut self.scene.animation;
+
+        if kind.value == "camera" {
+            let camera = match name {
+                Some(name) => match self.camera_names.get(&name.value) {
+                    Some(i) => *i,
+                    None => {
+                        return error(name.line, name.column, format!("unknown camera '{}'", name.value))
+                    }
+                },
+                None => 0,
+            };
+            if animation.cameras.iter().any(|animation| animation.camera == camera) {
+                return node.error("this camera is already animated".into());
+            }
+            animation.cameras.push(CameraAnimation {
+                camera,
+                pos: vectors("pos")?,
+                rot: vectors("rot")?,
+                fov: floats("fov")?,
+                focal_length: floats("focal_length")?,
+                aperture_radius: floats("aperture_radius")?,
+            });
+        } else {
+            let name = match name {
+                Some(name) => name,
+                None => return node.error("'animate sphere' needs the name of a sphere".into()),
+            };
+            let sphere = match self.sphere_names.get(&name.value) {
+                Some(i) => *i,
+                None => return error(name.line, name.column, format!("unknown sphere '{}'", name.value)),
+            };
+            if animation.spheres.iter().any(|animation| animation.sphere == sphere) {
+                return node.error(format!("'{}' is already animated", name.value));
+            }
+            if let Some(pos) = vectors("pos")? {
+                animation.spheres.push(SphereAnimation { sphere, pos });
+            }
+        }
+        Ok(())
+    }
+
+    fn wireframe(&mut self, node: &Node) -> Result<(), ParseError> {
+        node.name()?;
+        let mut wireframe = Wireframe::new(vec![]);
+
+        for child in node.children()? {
+            match &child.key[..] {
+                "line" => {
+                    child.arg_count(&[0])?;
+                    let (mut from, mut to) =
+                        (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+                    let mut color = Col::new(1.0, 1.0, 1.0);
+                    for property in child.children()? {
+                        match &property.key[..] {
+                            "from" => from = property.vector()?,
+                            "to" => to = property.vector()?,
+                            "color" => color = property.color()?,
+                            _ => return property.unknown("line"),
+                        }
+                    }
+                    wireframe.lines.push(Line3d::new(from, to, color));
+                }
+                "globe" => {
+                    child.arg_count(&[0])?;
+                    let mut center = Vector3::new(0.0, 0.0, 0.0);
+                    let mut radius = 1.0;
+                    let mut rings = 8;
+                    let mut color = Col::new(1.0, 1.0, 1.0);
+                    for property in child.children()? {
+                        match &property.key[..] {
+                            "center" => center = property.vector()?,
+                            "around" => {
+                                let name = property.string()?;
+                                match self.sphere_names.get(&name) {
+                                    Some(i) => {
+                                        center = self.scene.spheres[*i].pos;
+                                        radius = self.scene.spheres[*i].radius;
+                                    }
+                                    None => {
+                                        return property.error(format!("unknown sphere '{}'", name))
+                                    }
+                                }
+                            }
+                            "radius" => radius = property.float()?,
+                            "rings" => rings = property.count()?,
+                            "color" => color = property.color()?,
+                            _ => return property.unknown("globe"),
+                        }
+                    }
+                    let globe = Wireframe::globe(center, radius, rings, color);
+                    wireframe.lines.extend(globe.lines);
+                }
+                _ => return child.unknown("wireframe"),
+            }
+        }
+
+        self.scene.wireframes.push(wireframe);
+        Ok(())
+    }
+}
+
+// A track of the keys that set a property, or None if no key does
+fn track<T>(
+    keys: &[(f32, &Node)],
+    property: &str,
+    interpolation: Interpolation,
+    value: fn(&Node) -> Result<T, ParseError>,
+) -> Result<Option<Track<T>>, ParseError> {
+    let mut track = Track {
+        keys: vec![],
+        interpolation,
+    };
+    for (time, key) in keys {
+        for child in key.children()? {
+            if child.key == property {
+                track.keys.push((*time, value(child)?));
+            }
+        }
+    }
+
+    if track.keys.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(track))
+    }
+}
@@ -3,6 +3,10 @@ use crate::scene::{Ray, Scene};
 use std::cmp::Ord;
 
 pub fn sky_box(scene: &Scene, ray: &Ray) -> Col {
+    if let Some(environment) = &scene.sky.environment {
+        return environment.sample_equirectangular(ray.dir) * scene.sky.intensity;
+    }
+
     let mut col = mix_col(scene.sky.colors[0], scene.sky.colors[1], ray.dir.z.abs());
     fn tile(x: f32) -> f32 {
         if x > 0.99 {
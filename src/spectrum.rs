@@ -0,0 +1,144 @@
+use crate::helpers::{clamp, Col};
+
+// Spectral rendering. Each camera sample carries three wavelengths instead of red, green and
+// blue: a hero wavelength picked at random, and two more spread evenly across the visible
+// range after it. Along the path the channels of a Col hold the light at those wavelengths,
+// the scene's RGB colors are turned into spectra on the way in, and the result is turned
+// back into RGB with the CIE color matching functions at the end.
+
+// The visible range the wavelengths are picked from, in nanometers
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 730.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f32; 3],
+    // Only the hero wavelength is left, once something has sent the wavelengths different ways
+    pub hero_only: bool,
+}
+
+impl Wavelengths {
+    // The wavelengths for a hero at u, from 0 at the blue end of the range to 1 at the red end
+    pub fn hero(u: f32, hero_only: bool) -> Wavelengths {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let mut lambda = [0.0; 3];
+        for (i, lambda) in lambda.iter_mut().enumerate() {
+            *lambda = MIN_WAVELENGTH + ((u + i as f32 / 3.0) % 1.0) * range;
+        }
+        Wavelengths { lambda, hero_only }
+    }
+
+    // An RGB color, as the reflectance or emission at the wavelengths
+    pub fn spectrum(&self, col: Col) -> Col {
+        Col::new(
+            rgb_to_spectrum(col, self.lambda[0]),
+            rgb_to_spectrum(col, self.lambda[1]),
+            rgb_to_spectrum(col, self.lambda[2]),
+        )
+    }
+
+    // The index of refraction of glass at the wavelengths, 1.5 in the middle of the range
+    // and higher for bluer light (Cauchy's equation)
+    pub fn glass_ior(&self) -> [f32; 3] {
+        let ior = |lambda: f32| 1.5 + 4200.0 * (1.0 / (lambda * lambda) - 1.0 / (555.0 * 555.0));
+        [ior(self.lambda[0]), ior(self.lambda[1]), ior(self.lambda[2])]
+    }
+
+    // The light at the wavelengths as a linear RGB color. A flat spectrum comes out white on
+    // average over many samples.
+    pub fn to_rgb(&self, radiance: Col) -> Col {
+        let values = [radiance.r, radiance.g, radiance.b];
+        let count = if self.hero_only { 1 } else { 3 };
+
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for (&lambda, &value) in self.lambda.iter().zip(&values).take(count) {
+            let (x_bar, y_bar, z_bar) = cie_xyz(lambda);
+            x += value * x_bar;
+            y += value * y_bar;
+            z += value * z_bar;
+        }
+
+        let scale = (MAX_WAVELENGTH - MIN_WAVELENGTH) / count as f32;
+        let rgb = xyz_to_rgb(x * scale, y * scale, z * scale);
+        Col::new(rgb.r / WHITE.r, rgb.g / WHITE.g, rgb.b / WHITE.b)
+    }
+}
+
+// The RGB of a flat spectrum of 1 across the range, by which results are divided to keep
+// the white balance
+const WHITE: Col = Col {
+    r: 128.363_54,
+    g: 101.544_33,
+    b: 97.050_08,
+};
+
+// The CIE 1931 color matching functions, as the multi-lobe Gaussian fit of Wyman, Sloan and
+// Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: f32) -> (f32, f32, f32) {
+    fn lobe(lambda: f32, mean: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+        let sigma = if lambda < mean { sigma_below } else { sigma_above };
+        (-0.5 * ((lambda - mean) / sigma).powi(2)).exp()
+    }
+
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// CIE XYZ to linear sRGB
+fn xyz_to_rgb(x: f32, y: f32, z: f32) -> Col {
+    Col::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+// Smits' spectra for turning RGB into a smooth spectrum, "An RGB to Spectrum Conversion for
+// Reflectances", in 10 bins from 380 to 720 nm
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// The value of an RGB color's spectrum at a wavelength. The white part of the color, then
+// the part two channels share, then what is left of the largest channel.
+pub fn rgb_to_spectrum(col: Col, lambda: f32) -> f32 {
+    // Interpolated between the middles of the bins
+    let bin = |spectrum: &[f32; 10]| {
+        let x = (lambda - 380.0) / 34.0 - 0.5;
+        let i = (x.floor().max(0.0) as usize).min(8);
+        let t = clamp(x - i as f32, 0.0, 1.0);
+        spectrum[i] * (1.0 - t) + spectrum[i + 1] * t
+    };
+    let (r, g, b) = (col.r, col.g, col.b);
+
+    let value = if r <= g && r <= b {
+        r * bin(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * bin(&SMITS_CYAN) + (b - g) * bin(&SMITS_BLUE)
+            } else {
+                (b - r) * bin(&SMITS_CYAN) + (g - b) * bin(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * bin(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * bin(&SMITS_MAGENTA) + (b - r) * bin(&SMITS_BLUE)
+            } else {
+                (b - g) * bin(&SMITS_MAGENTA) + (r - b) * bin(&SMITS_RED)
+            }
+    } else {
+        b * bin(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * bin(&SMITS_YELLOW) + (g - r) * bin(&SMITS_GREEN)
+            } else {
+                (g - b) * bin(&SMITS_YELLOW) + (r - g) * bin(&SMITS_RED)
+            }
+    };
+    value.max(0.0)
+}
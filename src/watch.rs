@@ -0,0 +1,46 @@
+use crate::scene::Scene;
+use crate::scene_file::{load_scene, SceneError};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Watches a scene file by polling its modification time
+pub struct SceneWatcher {
+    path: PathBuf,
+    seed: u64,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl SceneWatcher {
+    pub fn new(path: &Path, seed: u64) -> SceneWatcher {
+        SceneWatcher {
+            path: path.to_path_buf(),
+            seed,
+            modified: modified(path),
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Re-parse the scene if the file changed since it was last seen
+    pub fn poll(&mut self) -> Option<Result<Scene, SceneError>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(load_scene(&self.path, self.seed))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,75 @@
+// Anamorphic lenses: a squeezed render, stretched back out, sees what a wider spherical
+// camera sees, with out of focus highlights taller than they are wide.
+
+mod common;
+
+use common::{gray, moments, scene};
+use simple_rust_cpu_raytracer::image::desqueeze;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, Pass, Renderer};
+use std::path::Path;
+
+const HEIGHT: usize = 20;
+
+fn renderer(camera: &str, objects: &str, width: usize, sky: f32) -> Renderer {
+    common::renderer(scene(camera, gray(sky), objects), width, HEIGHT)
+}
+
+fn black_sphere(x: f32) -> String {
+    format!("sphere {{ pos {} 10 0\n radius 1\n material {{ color 0 0 0 }} }}", x)
+}
+
+#[test]
+fn desqueezed_renders_match_a_wider_spherical_camera() {
+    let objects = black_sphere(6.0);
+
+    // Stretched out to twice the width, the squeezed render has the sphere where a spherical
+    // camera with twice the image width has it
+    let squeezed = renderer("aperture_radius 0\n anamorphic_squeeze 2", &objects, HEIGHT, 1.0);
+    let (image, width) = desqueeze(&squeezed.aov(Pass::Beauty, 4), HEIGHT, HEIGHT, 2.0);
+    assert_eq!((width, image.len()), (HEIGHT * 2, HEIGHT * HEIGHT * 2));
+    let spherical = renderer("aperture_radius 0", &objects, HEIGHT * 2, 1.0).aov(Pass::Beauty, 4);
+
+    let dark = |col: Col| 1.0 - col.r;
+    let (x, y, _, _) = moments(&image, width, dark);
+    let (expected_x, expected_y, _, _) = moments(&spherical, width, dark);
+    assert!((x - expected_x).abs() < 1.0, "{} instead of {}", x, expected_x);
+    assert!((y - expected_y).abs() < 1.0, "{} instead of {}", y, expected_y);
+
+    // Autofocus finds the sphere in the middle, just as without the squeeze
+    let mut focused = renderer("aperture_radius 0.5\n anamorphic_squeeze 2", &black_sphere(0.0), HEIGHT, 1.0);
+    focused.autofocus();
+    assert!((focused.camera().focal_length - 9.0).abs() < 0.05, "{}", focused.camera().focal_length);
+}
+
+#[test]
+fn the_squeeze_must_be_positive() {
+    let error = parse_scene("camera { anamorphic_squeeze 0 }", Path::new("."), 1).unwrap_err();
+    assert!(error.to_string().contains("more than 0"));
+}
+
+#[test]
+fn out_of_focus_highlights_are_ovals() {
+    // A light far beyond the focus, looking up so that only the black sky is behind it
+    let light = "sphere { pos 0 28.28 28.28\n radius 1\n material { color 0 0 0\n emission_color 1 1 1\n emission_intensity 100 } }";
+    let camera = |squeeze: f32| {
+        format!(
+            "fov 20\n rot 0.7854 0 0\n focal_length 12\n aperture_radius 1\n anamorphic_squeeze {}",
+            squeeze
+        )
+    };
+    let brightness = |col: Col| col.r;
+
+    let round = renderer(&camera(1.0), light, HEIGHT, 0.0).aov(Pass::Beauty, 256);
+    let (_, _, variance_x, variance_y) = moments(&round, HEIGHT, brightness);
+    let ratio = (variance_y / variance_x).sqrt();
+    assert!((ratio - 1.0).abs() < 0.15, "{}", ratio);
+
+    // Twice as tall as it is wide once stretched out, give or take the few pixels across
+    // that the narrow side is rendered at
+    let squeezed = renderer(&camera(2.0), light, HEIGHT, 0.0).aov(Pass::Beauty, 256);
+    let (image, width) = desqueeze(&squeezed, HEIGHT, HEIGHT, 2.0);
+    let (_, _, variance_x, variance_y) = moments(&image, width, brightness);
+    let ratio = (variance_y / variance_x).sqrt();
+    assert!((1.5..2.2).contains(&ratio), "{}", ratio);
+}
@@ -0,0 +1,102 @@
+// Keyframed animation: track interpolation, frame file names and animate blocks in scene files.
+
+use cgmath::{InnerSpace, Vector3};
+use simple_rust_cpu_raytracer::animation::{frame_path, Interpolation, Track};
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use std::path::{Path, PathBuf};
+
+const SCENE: &str = "
+camera main {
+    pos 0 -6 1
+    fov 60
+}
+
+sphere ball {
+    pos 0 0 0
+    radius 1
+}
+
+animate camera main {
+    key 1 {
+        fov 60
+    }
+    key 3 {
+        fov 40
+    }
+}
+
+animate sphere ball {
+    interpolation linear
+    key 0 {
+        pos 0 0 0
+    }
+    key 2 {
+        pos 4 0 2
+    }
+}
+";
+
+#[test]
+fn tracks_pass_through_their_keys() {
+    for &interpolation in &[Interpolation::Linear, Interpolation::Smooth] {
+        let track = Track {
+            keys: vec![(0.0, 1.0), (1.0, 3.0), (3.0, 2.0)],
+            interpolation,
+        };
+        for &(time, value) in &track.keys {
+            assert!((track.sample(time) - value).abs() < 1e-6);
+        }
+        // Holds still outside the keys
+        assert_eq!(track.sample(-1.0), 1.0);
+        assert_eq!(track.sample(10.0), 2.0);
+    }
+
+    let linear = Track {
+        keys: vec![(0.0, 0.0), (2.0, 4.0)],
+        interpolation: Interpolation::Linear,
+    };
+    assert!((linear.sample(0.5) - 1.0).abs() < 1e-6);
+
+    // Smooth eases in and out of the first and last key
+    let smooth = Track { interpolation: Interpolation::Smooth, ..linear };
+    assert!(smooth.sample(0.5) < 1.0);
+    assert!((smooth.sample(1.0) - 2.0).abs() < 1e-6);
+    assert!(smooth.sample(1.5) > 3.0);
+}
+
+#[test]
+fn frame_paths() {
+    let path = |pattern: &str, frame| frame_path(Path::new(pattern), frame);
+    assert_eq!(path("frames/shot_####.png", 7), PathBuf::from("frames/shot_0007.png"));
+    assert_eq!(path("a#b_##.png", 12345), PathBuf::from("a#b_12345.png"));
+    assert_eq!(path("shot.png", 3), PathBuf::from("shot_0003.png"));
+    assert_eq!(path("shot", 3), PathBuf::from("shot_0003"));
+}
+
+#[test]
+fn animate_blocks_move_the_scene() {
+    let mut scene = parse_scene(SCENE, Path::new("."), 1).unwrap();
+    let animation = scene.animation.clone();
+    assert_eq!(animation.duration(), 3.0);
+    assert!(!animation.animates_focal_length(0));
+
+    animation.apply(&mut scene, 1.0);
+    assert!((scene.spheres[0].pos - Vector3::new(2.0, 0.0, 1.0)).magnitude() < 1e-6);
+    assert_eq!(scene.cameras[0].fov, 60.0);
+
+    animation.apply(&mut scene, 5.0);
+    assert_eq!(scene.spheres[0].pos, Vector3::new(4.0, 0.0, 2.0));
+    assert_eq!(scene.cameras[0].fov, 40.0);
+
+    let valid = "camera main { pos 0 0 0 }\nanimate camera main { key 0 { fov 60 } }";
+    assert!(parse_scene(valid, Path::new("."), 1).is_ok());
+    let errors = [
+        "animate sphere missing { key 0 { pos 0 0 0 } }",
+        "animate camera main { key 0 { fov 60 } key 0 { fov 50 } }",
+        "animate camera main { key 0 { color 1 1 1 } }",
+    ];
+    for error in &errors {
+        let source = format!("camera main {{ pos 0 0 0 }}\n{}", error);
+        assert!(parse_scene(&source, Path::new("."), 1).is_err(), "{}", error);
+    }
+}
@@ -0,0 +1,83 @@
+// Aperture shapes: points on the lens fill the polygon or mask evenly and nothing else.
+
+use simple_rust_cpu_raytracer::aperture::Aperture;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+// Points for an even grid of random numbers
+fn samples(aperture: &Aperture) -> Vec<(f32, f32)> {
+    let steps = 100;
+    let mut points = vec![];
+    for i in 0..steps {
+        for j in 0..steps {
+            let u1 = (i as f32 + 0.5) / steps as f32;
+            let u2 = (j as f32 + 0.5) / steps as f32;
+            points.push(aperture.sample(u1, u2));
+        }
+    }
+    points
+}
+
+#[test]
+fn blades_fill_the_polygon_evenly() {
+    let count = 5;
+    let aperture = Aperture::Blades {
+        count,
+        rotation: 20.0,
+        curvature: 0.0,
+    };
+
+    // How far out a point is, as a fraction of the way to the blades
+    let apothem = (PI / count as f32).cos();
+    let extent = |(x, y): (f32, f32)| {
+        (0..count)
+            .map(|blade| {
+                let angle = (blade as f32 * 2.0 + 1.0) * PI / count as f32 + PI / 2.0 + 20f32.to_radians();
+                (-x * angle.cos() - y * angle.sin()) / apothem
+            })
+            .fold(0.0, f32::max)
+    };
+
+    let points = samples(&aperture);
+    assert!(points.iter().all(|&point| extent(point) < 1.0 + 1e-4));
+    assert!(points.iter().any(|&(x, y)| (x * x + y * y).sqrt() > 0.98));
+
+    // A polygon half the size holds a quarter of the points
+    let inner = points.iter().filter(|&&point| extent(point) < 0.5).count() as f32;
+    assert!((inner / points.len() as f32 - 0.25).abs() < 0.01);
+
+    // Fully curved blades are the circle
+    let round = Aperture::Blades {
+        count,
+        rotation: 0.0,
+        curvature: 1.0,
+    };
+    for (x, y) in samples(&round) {
+        assert!((x * x + y * y).sqrt() < 1.0 + 1e-4);
+    }
+}
+
+#[test]
+fn masks_only_let_light_through_where_they_are_bright() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("aperture");
+    fs::create_dir_all(&dir).unwrap();
+
+    // Only the top left quarter of the lens is open
+    let mut pgm = b"P5\n# quarter\n4 4\n255\n".to_vec();
+    pgm.extend_from_slice(&[255, 255, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    fs::write(dir.join("quarter.pgm"), pgm).unwrap();
+
+    let scene = parse_scene("camera { aperture_mask \"quarter.pgm\" }", &dir, 1).unwrap();
+    // Turned around for the crossing rays, so that highlights show the shape as given
+    for (x, y) in samples(&scene.camera().aperture) {
+        assert!((0.0..=1.0).contains(&x) && (-1.0..=0.0).contains(&y), "({}, {})", x, y);
+    }
+
+    let error = |source: &str| parse_scene(source, &dir, 1).unwrap_err().to_string();
+    assert!(error("camera { aperture_blades 2 }").contains("at least 3"));
+    assert!(error("camera { aperture_curvature 2 }").contains("from 0 to 1"));
+    assert!(error("camera { aperture_blades 6\n aperture_mask \"quarter.pgm\" }").contains("both"));
+    assert!(error("camera { aperture_mask \"missing.pgm\" }").contains("could not load"));
+}
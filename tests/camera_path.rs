@@ -0,0 +1,61 @@
+// Camera paths recorded in the viewer: the file format and playback as an animation.
+
+use cgmath::Vector3;
+use simple_rust_cpu_raytracer::animation::Animation;
+use simple_rust_cpu_raytracer::camera_path::CameraPath;
+use simple_rust_cpu_raytracer::scene::initialize_scene;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn saved_paths_load_unchanged() {
+    let mut camera = initialize_scene(0).cameras[0].clone();
+    let mut path = CameraPath::default();
+    for i in 0..5 {
+        camera.pos += Vector3::new(0.1, -0.3, 1.0 / 3.0);
+        camera.rot.z += 0.05;
+        camera.focal_length *= 0.9;
+        path.record(i as f32 / 7.0, &camera);
+    }
+    // Frames that don't move forward in time are dropped
+    path.record(0.2, &camera);
+    assert_eq!(path.frames.len(), 5);
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("camera_path");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("saved.path");
+    path.save(&file).unwrap();
+
+    assert_eq!(CameraPath::load(&file).unwrap().frames, path.frames);
+}
+
+#[test]
+fn playback_moves_between_frames() {
+    let path = CameraPath::parse(
+        "# time pos rot fov focal_length aperture_radius\n\
+         0 0 0 0  0 0 0  60 10 0\n\
+         1 2 0 4  0 0 1  80 20 0.5\n",
+    )
+    .unwrap();
+    let animation = Animation {
+        cameras: vec![path.animation(0)],
+        spheres: vec![],
+    };
+    assert_eq!(animation.duration(), 1.0);
+    assert!(animation.animates_focal_length(0));
+
+    let mut scene = initialize_scene(0);
+    animation.apply(&mut scene, 0.5);
+    let camera = &scene.cameras[0];
+    assert_eq!(camera.pos, Vector3::new(1.0, 0.0, 2.0));
+    assert_eq!(camera.rot, Vector3::new(0.0, 0.0, 0.5));
+    assert_eq!((camera.fov, camera.focal_length, camera.aperture_radius), (70.0, 15.0, 0.25));
+}
+
+#[test]
+fn parse_errors() {
+    assert!(CameraPath::parse("").unwrap_err().contains("no frames"));
+    assert!(CameraPath::parse("0 1 2 3").unwrap_err().contains("expected 10 numbers"));
+    let backwards = "1 0 0 0 0 0 0 60 10 0\n0.5 0 0 0 0 0 0 60 10 0\n";
+    assert!(CameraPath::parse(backwards).unwrap_err().starts_with("line 2"));
+}
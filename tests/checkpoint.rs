@@ -0,0 +1,132 @@
+// Checkpoints: a render that is stopped and resumed must match one that ran straight through.
+
+use simple_rust_cpu_raytracer::aperture::Aperture;
+use simple_rust_cpu_raytracer::checkpoint::{scene_hash, Checkpoint};
+use simple_rust_cpu_raytracer::progress::StopConditions;
+use simple_rust_cpu_raytracer::scene_file::load_scene;
+use simple_rust_cpu_raytracer::{Col, Pass, RenderSettings};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+fn scene(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/scenes")
+        .join(format!("{}.scene", name))
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("checkpoint");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str]) {
+    let result = Command::new(env!("CARGO_BIN_EXE_simple_rust_cpu_raytracer"))
+        .arg("--headless")
+        .args(args)
+        .output()
+        .expect("failed to run the renderer");
+
+    assert!(
+        result.status.success(),
+        "render failed:\n{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+}
+
+#[test]
+fn resumed_render_matches_uninterrupted_render() {
+    let scene = scene("diffuse");
+    let scene = scene.to_str().unwrap();
+    let dir = output_dir();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let size = ["-W", "24", "-H", "16", "--seed", "3"];
+
+    run(&[&size[..], &["-s", scene, "--spp", "12", "-o", &path("full.pfm")]].concat());
+    let checkpoint = ["--checkpoint", &path("part.checkpoint")];
+    run(&[&size[..], &["-s", scene, "--spp", "5", "-o", &path("part.pfm")], &checkpoint].concat());
+    run(&["-s", scene, "--spp", "12", "-o", &path("resumed.pfm"), "--resume", &path("part.checkpoint")]);
+
+    // Resuming keeps saving to the same checkpoint
+    let checkpoint = Checkpoint::load(Path::new(&path("part.checkpoint"))).unwrap();
+    assert_eq!(checkpoint.samples, 12);
+    assert_eq!((checkpoint.settings.width, checkpoint.settings.height), (24, 16));
+    assert_eq!(fs::read(path("full.pfm")).unwrap(), fs::read(path("resumed.pfm")).unwrap());
+
+    // Without stop conditions of its own, a resumed render stops where it was meant to
+    run(&["-s", scene, "-o", &path("again.pfm"), "--resume", &path("part.checkpoint")]);
+    let checkpoint = Checkpoint::load(Path::new(&path("part.checkpoint"))).unwrap();
+    assert_eq!((checkpoint.samples, checkpoint.stop.samples), (12, Some(12)));
+}
+
+#[test]
+fn scene_hash_follows_the_scene() {
+    let diffuse = load_scene(&scene("diffuse"), 1).unwrap();
+    let metallic = load_scene(&scene("metallic"), 1).unwrap();
+
+    assert_eq!(scene_hash(&diffuse), scene_hash(&load_scene(&scene("diffuse"), 1).unwrap()));
+    assert_ne!(scene_hash(&diffuse), scene_hash(&metallic));
+
+    // The checkpoint doesn't hold the shape of the aperture, so the hash has to
+    let mut bladed = load_scene(&scene("diffuse"), 1).unwrap();
+    bladed.camera_mut().aperture = Aperture::Blades {
+        count: 6,
+        rotation: 0.0,
+        curvature: 0.0,
+    };
+    assert_ne!(scene_hash(&diffuse), scene_hash(&bladed));
+}
+
+#[test]
+fn checkpoints_need_every_header_field() {
+    let scene = load_scene(&scene("diffuse"), 1).unwrap();
+    let path = output_dir().join("fields.checkpoint");
+    let black = vec![Col::new(0.0, 0.0, 0.0); 2];
+    let settings = RenderSettings {
+        width: 2,
+        height: 1,
+        max_bounces: 4,
+        max_wormhole_bounces: 4,
+        chromatic_aberration_strength: 0.0,
+        spectral: false,
+        seed: 1,
+    };
+    let stop = StopConditions {
+        samples: None,
+        time_budget: Some(Duration::from_millis(1500)),
+        noise_threshold: Some(0.02),
+    };
+    Checkpoint {
+        settings,
+        pass: Pass::Beauty,
+        samples: 1,
+        stop,
+        scene_hash: scene_hash(&scene),
+        camera: scene.camera().clone(),
+        render_buffer: black.clone(),
+        half_buffer: black,
+    }
+    .save(&path)
+    .unwrap();
+    let saved = fs::read(&path).unwrap();
+    assert_eq!(Checkpoint::load(&path).unwrap().stop, stop);
+
+    // Change the header, keep the pixels after it
+    let edit = |change: &dyn Fn(&str) -> String| {
+        let end = saved.windows(5).position(|bytes| bytes == b"\nend\n").unwrap() + 1;
+        let mut data = change(std::str::from_utf8(&saved[..end]).unwrap()).into_bytes();
+        data.extend_from_slice(&saved[end..]);
+        fs::write(&path, data).unwrap();
+        Checkpoint::load(&path).unwrap_err().to_string()
+    };
+    let missing = edit(&|header| header.replace("camera_convergence 0\n", ""));
+    assert_eq!(missing, "missing camera_convergence");
+    let malformed = edit(&|header| header.replace("camera_vignetting 0\n", "camera_vignetting dim\n"));
+    assert_eq!(malformed, "invalid value 'dim' for camera_vignetting");
+    let old = edit(&|header| header.replace("checkpoint 3", "checkpoint 2"));
+    assert!(old.starts_with("unsupported checkpoint version"), "{}", old);
+    let huge = edit(&|header| header.replace("\nwidth 2\n", "\nwidth 9223372036854775807\n"));
+    assert_eq!(huge, "the image size is too large");
+}
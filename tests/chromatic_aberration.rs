@@ -0,0 +1,43 @@
+// Chromatic aberration: samples of different wavelengths land apart, but add up to white.
+
+mod common;
+
+use common::{gray, scene, settings};
+use simple_rust_cpu_raytracer::{Col, Pass, RenderSettings, Renderer};
+
+const SIZE: usize = 16;
+
+// Render a black sphere to the side of the view, against a white sky
+fn render(strength: f32, samples: u32) -> Vec<Col> {
+    let sphere = "sphere { pos 3 10 0\n radius 1\n material { color 0 0 0 } }";
+    let scene = scene("aperture_radius 0", gray(1.0), sphere);
+    let settings = RenderSettings {
+        chromatic_aberration_strength: strength,
+        ..settings(SIZE, SIZE)
+    };
+    Renderer::new(scene, settings).aov(Pass::Beauty, samples)
+}
+
+#[test]
+fn tint_keeps_the_white_balance() {
+    // Only the sky is in the top rows
+    let image = render(2.0, 256);
+    let sky = &image[..SIZE * 3];
+    let n = sky.len() as f32;
+    let mean = sky.iter().fold(Col::new(0.0, 0.0, 0.0), |sum, col| sum + *col) * (1.0 / n);
+    for channel in &[mean.r, mean.g, mean.b] {
+        assert!((channel - 1.0).abs() < 0.05, "{:?}", mean);
+    }
+
+    // Without chromatic aberration the sky is exactly white
+    assert!(render(0.0, 4)[..SIZE * 3].iter().all(|col| col.r == 1.0 && col.g == 1.0 && col.b == 1.0));
+}
+
+#[test]
+fn colors_split_at_the_edges_of_objects() {
+    // The most red and blue differ by in any one pixel
+    let fringe = |image: &[Col]| image.iter().map(|col| (col.r - col.b).abs()).fold(0.0, f32::max);
+
+    assert!(fringe(&render(0.0, 64)) < 0.1);
+    assert!(fringe(&render(5.0, 64)) > 0.3);
+}
@@ -0,0 +1,23 @@
+// Command line options: values that make no sense are turned away before any rendering.
+
+use std::process::Command;
+
+// The error the renderer exits with for these arguments
+fn error(args: &str) -> String {
+    let result = Command::new(env!("CARGO_BIN_EXE_simple_rust_cpu_raytracer"))
+        .arg("--headless")
+        .args(args.split_whitespace())
+        .output()
+        .expect("failed to run the renderer");
+    assert_eq!(result.status.code(), Some(2), "{}", String::from_utf8_lossy(&result.stderr));
+    String::from_utf8_lossy(&result.stderr).lines().next().unwrap_or("").to_string()
+}
+
+#[test]
+fn numbers_must_be_positive_and_finite() {
+    assert_eq!(error("--fps inf"), "error: invalid value 'inf' for --fps");
+    assert_eq!(error("--anamorphic inf"), "error: invalid value 'inf' for --anamorphic");
+    assert_eq!(error("--noise NaN"), "error: --noise must be larger than zero");
+    assert_eq!(error("--fps 0"), "error: --fps must be larger than zero");
+    assert_eq!(error("--spp -1"), "error: invalid value '-1' for --spp");
+}
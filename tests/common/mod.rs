@@ -0,0 +1,67 @@
+// Small scenes and renderers shared by the camera tests. Every test file only uses some of them.
+#![allow(dead_code)]
+
+use simple_rust_cpu_raytracer::scene::Scene;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, RenderSettings, Renderer};
+use std::path::Path;
+
+// A camera at the origin looking along y, with a 60 degree field of view and whatever else
+// is given, under a sky of a single color, and the objects given in the scene format
+pub fn scene(camera: &str, sky: Col, objects: &str) -> Scene {
+    let source = format!(
+        "
+        camera {{
+            pos 0 0 0
+            fov 60
+            {}
+        }}
+        sky {{
+            color {sky}
+            color {sky}
+        }}
+        {}",
+        camera,
+        objects,
+        sky = format!("{} {} {}", sky.r, sky.g, sky.b)
+    );
+    parse_scene(&source, Path::new("."), 1).unwrap()
+}
+
+pub fn gray(value: f32) -> Col {
+    Col::new(value, value, value)
+}
+
+// A single bounce, plain RGB and a fixed seed, which is all the test scenes need
+pub fn settings(width: usize, height: usize) -> RenderSettings {
+    RenderSettings {
+        width,
+        height,
+        max_bounces: 1,
+        max_wormhole_bounces: 1,
+        chromatic_aberration_strength: 0.0,
+        spectral: false,
+        seed: 1,
+    }
+}
+
+pub fn renderer(scene: Scene, width: usize, height: usize) -> Renderer {
+    Renderer::new(scene, settings(width, height))
+}
+
+// The mean position of an image and its variance along both axes, weighted by a value for
+// each pixel, as (x, y, variance x, variance y)
+pub fn moments(image: &[Col], width: usize, weight: impl Fn(Col) -> f32) -> (f32, f32, f32, f32) {
+    let (mut total, mut x, mut y, mut xx, mut yy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (i, &col) in image.iter().enumerate() {
+        let (px, py, w) = ((i % width) as f32, (i / width) as f32, weight(col));
+        total += w;
+        x += w * px;
+        y += w * py;
+        xx += w * px * px;
+        yy += w * py * py;
+    }
+    assert!(total > 0.0, "nothing to weigh");
+    let (x, y) = (x / total, y / total);
+    (x, y, xx / total - x * x, yy / total - y * y)
+}
@@ -0,0 +1,122 @@
+// Lens distortion and vignetting: the render and its overlays bend the same way, and the
+// image darkens towards its edges.
+
+mod common;
+
+use common::gray;
+use simple_rust_cpu_raytracer::distortion::Distortion;
+use simple_rust_cpu_raytracer::scene::Scene;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, Pass};
+use std::path::Path;
+
+const SIZE: usize = 40;
+
+fn scene(camera: &str, objects: &str) -> Scene {
+    common::scene(&format!("rot 0.7854 0 0\n aperture_radius 0\n {}", camera), gray(1.0), objects)
+}
+
+fn render(scene: Scene, pass: Pass) -> Vec<Col> {
+    common::renderer(scene, SIZE, SIZE).aov(pass, 4)
+}
+
+// The mean position of the pixels selected
+fn centroid(selected: impl Iterator<Item = bool>) -> (f32, f32) {
+    let (mut x, mut y, mut count) = (0.0, 0.0, 0.0);
+    for (i, selected) in selected.enumerate() {
+        if selected {
+            x += (i % SIZE) as f32;
+            y += (i / SIZE) as f32;
+            count += 1.0;
+        }
+    }
+    assert!(count > 0.0, "nothing selected");
+    (x / count, y / count)
+}
+
+#[test]
+fn distortion_round_trips() {
+    let distortions = [
+        Distortion { k1: -0.05, ..Distortion::default() },
+        Distortion { k1: 0.1, k2: 0.02, ..Distortion::default() },
+        Distortion { k1: -0.02, k3: 0.001, p1: 0.01, p2: -0.005, ..Distortion::default() },
+    ];
+    for distortion in &distortions {
+        for &(x, y) in &[(0.0, 0.0), (0.3, -0.2), (-0.7, 0.45), (0.1, 0.5)] {
+            let (distorted_x, distorted_y) = distortion.distort(x, y);
+            let (undistorted_x, undistorted_y) = distortion.undistort(distorted_x, distorted_y).unwrap();
+            assert!((undistorted_x - x).abs() < 1e-4 && (undistorted_y - y).abs() < 1e-4);
+        }
+    }
+
+    // Barrel distortion pulls the edges in, and pincushion distortion pushes them out
+    assert!(distortions[0].distort(0.5, 0.0).0 < 0.5);
+    assert!(distortions[1].distort(0.5, 0.0).0 > 0.5);
+    // Strong barrel distortion folds the image into a circle, with nothing outside it
+    let strong = Distortion { k1: -0.3, ..Distortion::default() };
+    assert!(strong.undistort(1.0, 0.0).is_none());
+}
+
+#[test]
+fn lens_arguments_are_checked() {
+    let error = |source: &str| parse_scene(source, Path::new("."), 1).unwrap_err().to_string();
+    assert!(error("camera { distortion 1 2 3 4 }").contains("argument"));
+    assert!(error("camera { vignetting 2 }").contains("from 0 to 1"));
+    assert!(error("camera { optical_vignetting -1 }").contains("negative"));
+}
+
+#[test]
+fn overlays_line_up_with_the_distorted_render() {
+    // A black sphere off to the side, with a small cross of overlay lines on it, 10 ahead,
+    // 3 to the right and 2 up from the camera
+    let objects = "
+        sphere { pos 3 5.657 8.485\n radius 0.5\n material { color 0 0 0 } }
+        wireframe {
+            line { from 2.9 5.657 8.485\n to 3.1 5.657 8.485 }
+            line { from 3 5.728 8.414\n to 3 5.586 8.556 }
+        }";
+
+    let mut moved = 0.0;
+    for camera in &["", "distortion 0.6 0.1\n tangential_distortion 0.01 0.02"] {
+        let scene = scene(camera, objects);
+        let mut overlay = vec![0; SIZE * SIZE + 1];
+        scene.wireframes[0].render(&mut overlay, scene.camera(), SIZE, SIZE);
+        let (line_x, line_y) = centroid(overlay[..SIZE * SIZE].iter().map(|&pixel| pixel != 0));
+
+        let image = render(scene, Pass::Beauty);
+        let (sphere_x, sphere_y) = centroid(image.iter().map(|col| col.r < 0.5));
+        assert!(
+            (line_x - sphere_x).abs() < 1.5 && (line_y - sphere_y).abs() < 1.5,
+            "overlay at ({}, {}), sphere at ({}, {})",
+            line_x,
+            line_y,
+            sphere_x,
+            sphere_y
+        );
+        moved = sphere_x - moved;
+    }
+    // The distortion moved the sphere far enough for the test to mean something
+    assert!(moved.abs() > 2.0, "{}", moved);
+}
+
+#[test]
+fn vignetting_darkens_the_edges() {
+    let brightness = |image: &[Col], x: usize, y: usize| image[y * SIZE + x].r;
+
+    // The cos^4 falloff, of the angle out to the corner of the image
+    let image = render(scene("vignetting 1", ""), Pass::Beauty);
+    assert!((brightness(&image, SIZE / 2, SIZE / 2) - 1.0).abs() < 0.01);
+    let tan = (30f32.to_radians().tan() * 2f32.sqrt()).atan();
+    let corner = brightness(&image, 0, 0);
+    assert!((corner - tan.cos().powi(4)).abs() < 0.05, "{}", corner);
+
+    // The lens barrel hides part of the lens away from the middle, and nothing in the middle
+    let image = render(scene("aperture_radius 0.1\n optical_vignetting 1", ""), Pass::Beauty);
+    assert!((brightness(&image, SIZE / 2, SIZE / 2) - 1.0).abs() < 0.01);
+    assert!(brightness(&image, 0, SIZE / 2) < 0.9);
+
+    // Depth isn't light, and isn't vignetted
+    let plain = render(scene("", ""), Pass::Depth);
+    let vignetted = render(scene("vignetting 1\n optical_vignetting 1", ""), Pass::Depth);
+    assert_eq!(plain[0].r, vignetted[0].r);
+}
@@ -0,0 +1,143 @@
+// A coordinator with several worker processes on this machine must produce the same
+// image as a local headless render.
+
+use simple_rust_cpu_raytracer::distributed::{coordinate, work, Event, Job};
+use simple_rust_cpu_raytracer::scene_file::load_scene;
+use simple_rust_cpu_raytracer::{Col, Pass, RenderSettings, Renderer};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const WORKERS: usize = 3;
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("distributed");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn renderer() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_simple_rust_cpu_raytracer"))
+}
+
+#[test]
+fn workers_match_local_render() {
+    let local = output_dir().join("local.pfm");
+    let distributed = output_dir().join("distributed.pfm");
+    let scene = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenes/emissive.scene");
+    let settings = ["-s", scene.to_str().unwrap(), "-W", "40", "-H", "30", "--spp", "6", "--seed", "4"];
+
+    let status = renderer()
+        .arg("--headless")
+        .args(settings)
+        .args(["-o", local.to_str().unwrap()])
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut coordinator = renderer()
+        .args(["--coordinator", "127.0.0.1:0", "--tile-size", "8"])
+        .args(settings)
+        .args(["-o", distributed.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The coordinator picks a free port and prints it
+    let mut lines = BufReader::new(coordinator.stdout.take().unwrap()).lines();
+    let address = lines
+        .by_ref()
+        .map(|line| line.unwrap())
+        .find_map(|line| line.strip_prefix("listening on ").map(String::from))
+        .expect("the coordinator didn't say where it listens");
+
+    let workers = (0..WORKERS)
+        .map(|_| {
+            renderer()
+                .args(["--worker", &address])
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    // Keep reading so the coordinator never blocks on a full pipe
+    lines.for_each(drop);
+    assert!(coordinator.wait().unwrap().success());
+    for mut worker in workers {
+        assert!(worker.wait().unwrap().success());
+    }
+
+    assert_eq!(fs::read(local).unwrap(), fs::read(distributed).unwrap());
+}
+
+#[test]
+fn jobs_of_a_silent_worker_go_to_another() {
+    let scene = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenes/emissive.scene");
+    let scene = load_scene(&scene, 1).unwrap();
+    let settings = RenderSettings {
+        width: 16,
+        height: 8,
+        max_bounces: 2,
+        max_wormhole_bounces: 2,
+        chromatic_aberration_strength: 0.0,
+        spectral: false,
+        seed: 4,
+    };
+    let jobs = Job::split(16, 8, 8, 2, 2);
+    let total = jobs.len();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let lost = Mutex::new(vec![]);
+    let on_event = |event: Event| {
+        if let Event::WorkerLost(worker, e) = event {
+            lost.lock().unwrap().push((worker, e.kind()));
+        }
+    };
+
+    let (buffer, silent, jobs_done) = thread::scope(|scope| {
+        // Connects and takes a job, but never answers
+        let silent = TcpStream::connect(address).unwrap();
+        let worker = scope.spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            work(TcpStream::connect(address).unwrap()).unwrap()
+        });
+        let timeout = Duration::from_millis(500);
+        let buffer = coordinate(&listener, &scene, &settings, Pass::Beauty, jobs, timeout, &on_event).unwrap();
+        (buffer, silent, worker.join().unwrap())
+    });
+
+    assert_eq!(jobs_done, total);
+    let silent = silent.local_addr().unwrap();
+    assert_eq!(lost.into_inner().unwrap(), vec![(silent, std::io::ErrorKind::TimedOut)]);
+
+    let mut local = vec![Col::new(0.0, 0.0, 0.0); 16 * 8];
+    Renderer::new(scene, settings).render(Pass::Beauty, &mut local, 0, 2);
+    let channels = |image: &[Col]| image.iter().flat_map(|col| [col.r, col.g, col.b]).collect::<Vec<f32>>();
+    assert_eq!(channels(&buffer), channels(&local));
+}
+
+#[test]
+fn workers_refuse_images_too_large_to_hold() {
+    // A coordinator asking for an image 65536 pixels on a side
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let coordinator = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"RTDIST09").unwrap();
+        for size in [65536u32, 65536] {
+            stream.write_all(&size.to_le_bytes()).unwrap();
+        }
+    });
+
+    let error = work(TcpStream::connect(address).unwrap()).unwrap_err();
+    coordinator.join().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("too large"), "{}", error);
+}
@@ -0,0 +1,198 @@
+// Golden-image regression tests.
+//
+// Each test renders a small reference scene headlessly with a fixed seed and sample
+// count, then compares the raw radiance against a committed PFM image in tests/golden.
+// On failure a diff image is written next to the render in the target directory.
+//
+// To accept new reference images after an intentional change, run:
+//
+//     UPDATE_GOLDEN=1 cargo test --test golden
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const WIDTH: usize = 48;
+const HEIGHT: usize = 48;
+const SAMPLES: u32 = 16;
+const SEED: u64 = 1;
+
+// Largest difference a single channel may have before the pixel counts as wrong
+const PIXEL_TOLERANCE: f32 = 0.1;
+// Share of pixels allowed to exceed the pixel tolerance
+const MAX_WRONG_PIXELS: f32 = 0.01;
+// Root mean square error over all channels
+const RMSE_TOLERANCE: f32 = 0.01;
+
+struct Image {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+fn read_pfm(path: &Path) -> Image {
+    let bytes = fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+
+    // Three whitespace terminated header fields: "PF", "<width> <height>", "<scale>"
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut pos = 0;
+    while fields.len() < 4 {
+        if bytes[pos].is_ascii_whitespace() {
+            if pos > start {
+                fields.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+            }
+            start = pos + 1;
+        }
+        pos += 1;
+    }
+
+    assert_eq!(fields[0], "PF", "{} is not a colour PFM", path.display());
+    let width: usize = fields[1].parse().unwrap();
+    let height: usize = fields[2].parse().unwrap();
+    let little_endian = fields[3].starts_with('-');
+
+    let data = bytes[pos..]
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect::<Vec<f32>>();
+    assert_eq!(data.len(), width * height * 3, "{} is truncated", path.display());
+
+    Image {
+        width,
+        height,
+        data,
+    }
+}
+
+fn write_pfm(path: &Path, image: &Image) {
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", image.width, image.height).into_bytes();
+    for value in &image.data {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn render(scene: &str, pass: &str, output: &Path) {
+    let scene = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/scenes")
+        .join(format!("{}.scene", scene));
+
+    let result = Command::new(env!("CARGO_BIN_EXE_simple_rust_cpu_raytracer"))
+        .arg("--headless")
+        .args(["--scene", scene.to_str().unwrap()])
+        .args(["--width", &WIDTH.to_string()])
+        .args(["--height", &HEIGHT.to_string()])
+        .args(["--spp", &SAMPLES.to_string()])
+        .args(["--seed", &SEED.to_string()])
+        .args(["--pass", pass])
+        .args(["--output", output.to_str().unwrap()])
+        .output()
+        .expect("failed to run the renderer");
+
+    assert!(
+        result.status.success(),
+        "render failed:\n{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+}
+
+fn golden(scene: &str, pass: &str) {
+    let name = format!("{}_{}", scene, pass);
+    let output = output_dir().join(format!("{}.pfm", name));
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.pfm", name));
+
+    render(scene, pass, &output);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::copy(&output, &reference).unwrap();
+        return;
+    }
+
+    let actual = read_pfm(&output);
+    let expected = read_pfm(&reference);
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "{}: image size changed",
+        name
+    );
+
+    let diff = Image {
+        width: actual.width,
+        height: actual.height,
+        data: actual
+            .data
+            .iter()
+            .zip(&expected.data)
+            .map(|(a, b)| (a - b).abs())
+            .collect(),
+    };
+
+    let wrong_pixels = diff
+        .data
+        .chunks(3)
+        .filter(|pixel| pixel.iter().any(|d| d.is_nan() || *d > PIXEL_TOLERANCE))
+        .count();
+    let wrong_share = wrong_pixels as f32 / (diff.width * diff.height) as f32;
+    let rmse = (diff.data.iter().map(|d| d * d).sum::<f32>() / diff.data.len() as f32).sqrt();
+
+    if wrong_share > MAX_WRONG_PIXELS || rmse.is_nan() || rmse > RMSE_TOLERANCE {
+        let diff_path = output_dir().join(format!("{}_diff.pfm", name));
+        write_pfm(&diff_path, &diff);
+        panic!(
+            "{}: {} pixels differ by more than {} and the RMSE is {}\n  render: {}\n  diff:   {}",
+            name,
+            wrong_pixels,
+            PIXEL_TOLERANCE,
+            rmse,
+            output.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn diffuse() {
+    golden("diffuse", "beauty");
+}
+
+#[test]
+fn metallic_roughness() {
+    golden("metallic", "beauty");
+}
+
+#[test]
+fn emissive() {
+    golden("emissive", "beauty");
+}
+
+#[test]
+fn wormhole() {
+    golden("wormhole", "beauty");
+}
+
+#[test]
+fn depth_pass() {
+    golden("diffuse", "depth");
+}
+
+#[test]
+fn normal_pass() {
+    golden("diffuse", "normal");
+}
@@ -0,0 +1,69 @@
+// Radiance HDR files: what is written comes back within the precision of the RGBE encoding.
+
+use simple_rust_cpu_raytracer::hdr::{read_hdr, write_hdr};
+use simple_rust_cpu_raytracer::Col;
+use std::fs;
+use std::path::PathBuf;
+
+fn output(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("hdr");
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+// Every channel within the step of the shared exponent, 1/128 of the brightest channel
+fn assert_close(read: &[Col], written: &[Col]) {
+    assert_eq!(read.len(), written.len());
+    for (read, written) in read.iter().zip(written) {
+        let step = written.r.max(written.g).max(written.b) / 128.0;
+        for (a, b) in [(read.r, written.r), (read.g, written.g), (read.b, written.b)] {
+            assert!((a - b).abs() <= step, "{:?} instead of {:?}", read, written);
+        }
+    }
+}
+
+#[test]
+fn run_length_encoded_scanlines_round_trip() {
+    // Wide enough for run-length encoding, with runs of equal pixels, a run of black and
+    // pixels that change every time
+    let (width, height) = (40, 3);
+    let image: Vec<Col> = (0..width * height)
+        .map(|i| match i % width {
+            x if x < 12 => Col::new(0.5, 0.25, 2.0),
+            x if x < 20 => Col::new(0.0, 0.0, 0.0),
+            x => Col::new(x as f32 * 0.37, (i / width) as f32 * 10.0 + 0.01, 1.0 / x as f32),
+        })
+        .collect();
+
+    let path = output("runs.hdr");
+    write_hdr(&path, &image, width, height).unwrap();
+    let read = read_hdr(&path).unwrap();
+    assert_eq!((read.width, read.height), (width, height));
+    assert_close(&read.pixels, &image);
+
+    // The scanlines really were run-length encoded, taking less than 4 bytes a pixel
+    let data = fs::read(&path).unwrap();
+    let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 3 +X 40\n";
+    assert_eq!(&data[..header.len()], header);
+    assert_eq!(&data[header.len()..header.len() + 4], &[2, 2, 0, 40]);
+    assert!(data.len() - header.len() < width * height * 4);
+}
+
+#[test]
+fn narrow_scanlines_are_stored_flat() {
+    let image = vec![Col::new(1.0, 0.5, 0.25), Col::new(0.0, 0.0, 0.0), Col::new(1000.0, 3.0, 0.0)];
+    let path = output("narrow.hdr");
+    write_hdr(&path, &image, 3, 1).unwrap();
+    let read = read_hdr(&path).unwrap();
+    assert_close(&read.pixels, &image);
+}
+
+#[test]
+fn oversized_images_are_refused_before_reading_pixels() {
+    let path = output("huge.hdr");
+    let header = "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4294967296 +X 4294967296\n";
+    fs::write(&path, header).unwrap();
+    let error = read_hdr(&path).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "image too large");
+}
@@ -0,0 +1,79 @@
+// Image files: the PNG, PPM and PFM writers produce what other programs expect to read.
+
+use simple_rust_cpu_raytracer::image::{desqueeze, read_pgm, write_pfm, write_png, write_ppm};
+use simple_rust_cpu_raytracer::Col;
+use std::fs;
+use std::path::PathBuf;
+
+fn output(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("image");
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+#[test]
+fn png_chunks_have_the_right_checksums() {
+    let path = output("pixel.png");
+    write_png(&path, &[0x00ff_8000], 1, 1).unwrap();
+    let data = fs::read(&path).unwrap();
+    let be = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+
+    assert_eq!(&data[..8], b"\x89PNG\r\n\x1a\n");
+    // IHDR: 13 bytes of header, then its CRC
+    assert_eq!(&data[12..16], b"IHDR");
+    assert_eq!(be(29), 0x9077_53de);
+
+    // IDAT: a single stored deflate block holding the filter byte and the pixel, then the
+    // Adler-32 of those, and the chunk's CRC
+    assert_eq!(&data[37..41], b"IDAT");
+    assert_eq!(be(33), 15);
+    assert_eq!(&data[41..52], &[0x78, 0x01, 1, 4, 0, 0xfb, 0xff, 0, 0xff, 0x80, 0x00]);
+    assert_eq!(be(52), 0x0401_0180);
+    assert_eq!(be(56), 0xe3c2_4ac3);
+
+    assert_eq!(&data[64..68], b"IEND");
+    assert_eq!(be(68), 0xae42_6082);
+    assert_eq!(data.len(), 72);
+}
+
+#[test]
+fn pfm_rows_are_stored_bottom_up() {
+    let path = output("rows.pfm");
+    let (top, bottom) = (Col::new(1.0, 0.0, 0.0), Col::new(0.0, 0.0, 0.5));
+    write_pfm(&path, &[top, bottom], 1, 2).unwrap();
+    let data = fs::read(&path).unwrap();
+
+    let header = b"PF\n1 2\n-1.0\n";
+    assert_eq!(&data[..header.len()], header);
+    let floats: Vec<f32> = data[header.len()..]
+        .chunks(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    assert_eq!(floats, vec![0.0, 0.0, 0.5, 1.0, 0.0, 0.0]);
+
+    let path = output("rows.ppm");
+    write_ppm(&path, &[0x0001_0203, 0x00ff_fefd], 2, 1).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"P6\n2 1\n255\n\x01\x02\x03\xff\xfe\xfd");
+}
+
+#[test]
+fn pgm_reader_rejects_truncated_files() {
+    let path = output("mask.pgm");
+    fs::write(&path, b"P5\n# a comment\n2 2\n255\n\x00\x40\x80\xff").unwrap();
+    let (width, height, values) = read_pgm(&path).unwrap();
+    assert_eq!((width, height), (2, 2));
+    assert_eq!(values, vec![0.0, 64.0 / 255.0, 128.0 / 255.0, 1.0]);
+
+    fs::write(&path, b"P5\n2 2\n255\n\x00\x40\x80").unwrap();
+    assert!(read_pgm(&path).unwrap_err().to_string().contains("end of image data"));
+    fs::write(&path, b"P5\n2 2\n").unwrap();
+    assert!(read_pgm(&path).unwrap_err().to_string().contains("end of header"));
+    fs::write(&path, b"P2\n1 1\n255\n0").unwrap();
+    assert!(read_pgm(&path).is_err());
+
+    // Stretching two columns out to four blends between them
+    let (image, width) = desqueeze(&[Col::new(0.0, 0.0, 0.0), Col::new(1.0, 1.0, 1.0)], 2, 1, 2.0);
+    assert_eq!(width, 4);
+    let reds: Vec<f32> = image.iter().map(|col| col.r).collect();
+    assert_eq!(reds, vec![0.0, 0.25, 0.75, 1.0]);
+}
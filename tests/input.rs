@@ -0,0 +1,136 @@
+// Drive the viewer's input handling from recorded input instead of a window.
+
+use simple_rust_cpu_raytracer::app::{timestamp, Time, Viewport};
+use simple_rust_cpu_raytracer::input::{handle_input, Key, ReplayInput};
+use simple_rust_cpu_raytracer::movement::Movement;
+use simple_rust_cpu_raytracer::scene::{initialize_scene, Scene, MAX_TILT};
+use simple_rust_cpu_raytracer::Col;
+use std::time::Duration;
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 8;
+
+fn viewport() -> Viewport {
+    Viewport {
+        overlays_enabled: true,
+        undistorted_overlays: false,
+        autofocus: true,
+        depth_pass: false,
+        normal_pass: false,
+        recording: false,
+        chromatic_aberration_strength: 0.0,
+        sample_iter: 5,
+        time: Time {
+            start: timestamp(),
+            prev: timestamp(),
+            sum: Duration::new(0, 0),
+            framecount: 0,
+        },
+    }
+}
+
+// Play every frame of the replay, returning the final viewport and scene
+fn replay(source: &str) -> (Viewport, Scene) {
+    let mut input = ReplayInput::parse(source).unwrap();
+    let mut viewport = viewport();
+    let mut scene = initialize_scene(0);
+    let mut movement = Movement::new(scene.camera());
+    let mut render_buffer = vec![Col::new(1.0, 1.0, 1.0); WIDTH * HEIGHT];
+    let mut keys_down = vec![];
+
+    while !input.finished() {
+        handle_input(
+            &input,
+            &mut viewport,
+            &mut scene,
+            &mut render_buffer,
+            &mut movement,
+            &mut keys_down,
+            (WIDTH, HEIGHT),
+        );
+        input.advance();
+    }
+
+    (viewport, scene)
+}
+
+#[test]
+fn moving_restarts_accumulation() {
+    let start = initialize_scene(0).cameras[0].pos;
+    let (viewport, scene) = replay("- - W\n- - W\n");
+
+    assert_eq!(viewport.sample_iter, 0);
+    assert!(scene.camera().pos != start);
+}
+
+#[test]
+fn toggles_fire_once_per_press() {
+    // Holding U for three frames toggles once, pressing it again toggles back
+    let (viewport, _) = replay("- - U\n- - U\n- - U\n");
+    assert!(!viewport.overlays_enabled);
+
+    let (viewport, _) = replay("- - U\n- -\n- - U\n");
+    assert!(viewport.overlays_enabled);
+
+    let (viewport, _) = replay("- - K\n- - K\n");
+    assert!(viewport.undistorted_overlays);
+    assert_eq!(viewport.sample_iter, 5);
+
+    // Recording doesn't touch the image
+    let (viewport, _) = replay("- - R\n- - R\n");
+    assert!(viewport.recording);
+    assert_eq!(viewport.sample_iter, 5);
+}
+
+#[test]
+fn idle_input_keeps_accumulating() {
+    let (viewport, scene) = replay("# nothing pressed\n- -\n\n- -\n");
+
+    assert_eq!(viewport.sample_iter, 5);
+    assert_eq!(scene.camera().pos, initialize_scene(0).cameras[0].pos);
+}
+
+#[test]
+fn cameras_can_be_added_and_cycled() {
+    // Add a camera and move it, then cycle through every camera back around to it
+    let demo = initialize_scene(0);
+    let cycle = "- - C\n- -\n".repeat(demo.cameras.len() + 1);
+    let (viewport, scene) = replay(&format!("- - V\n- - D\n{}", cycle));
+
+    assert_eq!(viewport.sample_iter, 0);
+    assert_eq!(scene.cameras.len(), demo.cameras.len() + 1);
+    assert_eq!(scene.active_camera, demo.cameras.len());
+    assert_eq!(scene.cameras[0].pos, demo.cameras[0].pos);
+    assert!(scene.camera().pos != demo.cameras[0].pos);
+    assert_eq!(scene.camera().fov, demo.cameras[0].fov);
+}
+
+#[test]
+fn chromatic_aberration_is_adjustable() {
+    let (viewport, _) = replay("- - P\n- - P\n- - P\n- - O\n");
+    assert!((viewport.chromatic_aberration_strength - 0.1).abs() < 1e-6);
+    assert_eq!(viewport.sample_iter, 0);
+
+    let (viewport, _) = replay("- - O\n");
+    assert_eq!(viewport.chromatic_aberration_strength, 0.0);
+}
+
+#[test]
+fn lens_shift_and_tilt_are_adjustable() {
+    let (viewport, scene) = replay("- - Y\n- - Y\n- - Q\n- - T\n- - F\n- - F\n");
+    let camera = scene.camera();
+    assert!((camera.shift.y - 0.02).abs() < 1e-6 && (camera.shift.x + 0.01).abs() < 1e-6);
+    assert_eq!((camera.tilt.x, camera.tilt.y), (1.0, -2.0));
+    assert_eq!(viewport.sample_iter, 0);
+
+    // The plane of focus can't be tilted until it lies along the view
+    let (_, scene) = replay(&"- - G\n".repeat(100));
+    assert_eq!(scene.camera().tilt.x, -MAX_TILT);
+}
+
+#[test]
+fn parse_errors() {
+    assert!(ReplayInput::parse("- - Hyperspace").unwrap_err().contains("unknown key"));
+    assert!(ReplayInput::parse("10").unwrap_err().starts_with("line 1"));
+    assert_eq!(Key::from_name(Key::LeftShift.name()), Some(Key::LeftShift));
+}
@@ -0,0 +1,84 @@
+// Stop conditions and the noise estimate, on a small render of a test scene.
+
+use simple_rust_cpu_raytracer::progress::{NoiseEstimator, Progress, StopConditions};
+use simple_rust_cpu_raytracer::{Col, Pass, RenderSettings, Renderer};
+use std::path::Path;
+use std::time::Duration;
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 32;
+
+fn renderer() -> Renderer {
+    let settings = RenderSettings {
+        width: WIDTH,
+        height: HEIGHT,
+        max_bounces: 3,
+        max_wormhole_bounces: 10,
+        chromatic_aberration_strength: 0.0,
+        spectral: false,
+        seed: 1,
+    };
+    let scene = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenes/diffuse.scene");
+    let mut renderer = Renderer::load(&scene, settings).unwrap();
+    renderer.autofocus();
+    renderer
+}
+
+// Render until the progress says it's done, returning the sample count
+fn render_until_done(renderer: &Renderer, progress: &mut Progress) -> u32 {
+    let mut buffer = vec![Col::new(0.0, 0.0, 0.0); WIDTH * HEIGHT];
+    let mut samples = 0;
+    while !progress.done() {
+        renderer.render(Pass::Beauty, &mut buffer, samples, 1);
+        samples += 1;
+        progress.add_sample(&buffer, samples);
+    }
+    samples
+}
+
+#[test]
+fn stops_at_sample_count() {
+    let mut progress = Progress::new(StopConditions {
+        samples: Some(5),
+        ..StopConditions::default()
+    });
+
+    assert_eq!(render_until_done(&renderer(), &mut progress), 5);
+    assert_eq!(progress.fraction(), Some(1.0));
+    // Without a noise threshold there's no noise estimate to keep up
+    assert!(progress.estimator.previous.is_empty());
+}
+
+#[test]
+fn noise_falls_with_more_samples() {
+    let renderer = renderer();
+    let mut buffer = vec![Col::new(0.0, 0.0, 0.0); WIDTH * HEIGHT];
+    let mut estimator = NoiseEstimator::default();
+    let mut noise = vec![];
+
+    for sample in 0..64 {
+        renderer.render(Pass::Beauty, &mut buffer, sample, 1);
+        estimator.add_sample(&buffer, sample + 1);
+        if sample + 1 == 8 || sample + 1 == 64 {
+            noise.push(estimator.noise(sample + 1).unwrap());
+        }
+    }
+
+    assert!(estimator.noise(7).is_none());
+    assert!(noise[1] < noise[0] * 0.6, "noise went from {} to {}", noise[0], noise[1]);
+}
+
+#[test]
+fn stops_at_noise_threshold() {
+    let renderer = renderer();
+    let threshold = 0.2;
+    let mut progress = Progress::new(StopConditions {
+        noise_threshold: Some(threshold),
+        // Fail rather than hang if the noise never gets there
+        time_budget: Some(Duration::from_secs(60)),
+        ..StopConditions::default()
+    });
+
+    render_until_done(&renderer, &mut progress);
+    assert!(progress.noise.unwrap() <= threshold);
+}
@@ -0,0 +1,75 @@
+// Camera projections: rays and the inverse mapping used by the wireframe overlay agree.
+
+mod common;
+
+use cgmath::{InnerSpace, Vector3};
+use common::{gray, renderer, scene};
+use simple_rust_cpu_raytracer::pathtrace::{project, projection_ray};
+use simple_rust_cpu_raytracer::scene::{initialize_scene, Camera, Projection};
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, Pass};
+use std::path::Path;
+
+const ASPECT: f32 = 2.0;
+
+fn camera(projection: Projection) -> Camera {
+    let mut camera = initialize_scene(0).cameras[0].clone();
+    camera.projection = projection;
+    camera.fov = 200.0;
+    camera
+}
+
+#[test]
+fn projecting_a_ray_finds_its_image_point() {
+    let projections = [
+        Projection::Perspective,
+        Projection::Orthographic,
+        Projection::Fisheye,
+        Projection::Equirectangular,
+    ];
+    for &projection in &projections {
+        let mut camera = camera(projection);
+        if projection == Projection::Perspective {
+            camera.fov = 90.0;
+        }
+
+        for &(x, y) in &[(0.0, 0.0), (0.3, -0.2), (-0.7, 0.3), (0.1, 0.4)] {
+            let (origin, dir) = projection_ray(&camera, x, y, ASPECT).unwrap();
+            assert!((dir.magnitude() - 1.0).abs() < 1e-5);
+
+            let (px, py) = project(&camera, origin + dir * 5.0, ASPECT).unwrap();
+            assert!(
+                (px - x).abs() < 1e-4 && (py - y).abs() < 1e-4,
+                "{:?} ({}, {}) came back as ({}, {})",
+                projection,
+                x,
+                y,
+                px,
+                py
+            );
+        }
+    }
+
+    // The middle of the image looks straight ahead
+    let (_, dir) = projection_ray(&camera(Projection::Equirectangular), 0.0, 0.0, ASPECT).unwrap();
+    assert!((dir - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-6);
+}
+
+#[test]
+fn fisheye_is_black_outside_its_image_circle() {
+    let scene = scene("projection fisheye\n fov 180", gray(1.0), "");
+    assert_eq!(scene.camera().projection, Projection::Fisheye);
+    let image = renderer(scene, 30, 10).aov(Pass::Beauty, 1);
+
+    // 180 degrees across the image height makes a circle two image heights wide
+    let black = |col: Col| col.r == 0.0 && col.g == 0.0 && col.b == 0.0;
+    assert!(!black(image[5 * 30 + 15]));
+    assert!(!black(image[5 * 30 + 6]));
+    assert!(black(image[5 * 30]));
+    assert!(black(image[0]));
+}
+
+#[test]
+fn unknown_projections_are_an_error() {
+    assert!(parse_scene("camera { projection pinhole }", Path::new("."), 1).is_err());
+}
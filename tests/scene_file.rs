@@ -0,0 +1,40 @@
+// Scene file errors point at the line and column where the problem is.
+
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use std::path::Path;
+
+fn error(source: &str) -> String {
+    parse_scene(source, Path::new("."), 1).unwrap_err().to_string()
+}
+
+#[test]
+fn bad_tokens_are_reported_where_they_are() {
+    let source = "camera {\n    pos 0 0 0\n}\nsphere {\n    radius big\n}\n";
+    assert_eq!(error(source), "5:12: expected a number, found 'big'");
+
+    assert_eq!(error("camera {\n    pos 0 0 0\n} sky {\n}\n"), "3:3: expected a new line after '}'");
+    assert_eq!(error("camera {\n    name \"front\n}\n"), "2:10: unterminated string");
+    assert_eq!(error("camera {\n}\n}\n"), "3:1: unexpected '}'");
+    assert_eq!(error("camera {\n    zoom 2\n}\n"), "2:5: unknown camera property 'zoom'");
+}
+
+#[test]
+fn unterminated_blocks_point_at_their_opening_brace() {
+    let source = "camera {\n    pos 0 0 0\n}\nsphere {\n    pos 0 1 0\n    material {\n        color 1 1 1\n}\n";
+    assert_eq!(error(source), "4:8: unclosed '{'");
+}
+
+#[test]
+fn scattered_spheres_depend_only_on_the_seed() {
+    let source = "camera {\n}\nscatter {\n    count 20\n    min -5 0 -5\n    max 5 10 5\n    radius 0.5 1\n}\n";
+    let positions = |seed: u64| -> Vec<_> {
+        let scene = parse_scene(source, Path::new("."), seed).unwrap();
+        scene.spheres.iter().map(|sphere| sphere.pos).collect()
+    };
+
+    let scattered = positions(7);
+    assert_eq!(scattered.len(), 20);
+    assert!(scattered.iter().all(|pos| pos.x.abs() <= 5.0 && (0.0..=10.0).contains(&pos.y)));
+    assert_eq!(scattered, positions(7));
+    assert_ne!(scattered, positions(8));
+}
@@ -0,0 +1,58 @@
+// Spectral rendering: colors go through spectra and come back out as the same colors.
+
+mod common;
+
+use common::{scene, settings};
+use simple_rust_cpu_raytracer::spectrum::Wavelengths;
+use simple_rust_cpu_raytracer::{Col, Pass, RenderSettings, Renderer};
+
+fn close(a: Col, b: Col, tolerance: f32) -> bool {
+    (a.r - b.r).abs() < tolerance && (a.g - b.g).abs() < tolerance && (a.b - b.b).abs() < tolerance
+}
+
+#[test]
+fn colors_survive_the_round_trip_through_a_spectrum() {
+    let colors = [
+        Col::new(1.0, 1.0, 1.0),
+        Col::new(0.5, 0.5, 0.5),
+        Col::new(1.0, 0.0, 0.0),
+        Col::new(0.0, 1.0, 0.0),
+        Col::new(0.0, 0.0, 1.0),
+        Col::new(0.3, 0.6, 0.9),
+        Col::new(0.9, 0.7, 0.1),
+    ];
+    let steps = 1000;
+    for &col in &colors {
+        for &hero_only in &[false, true] {
+            let mut sum = Col::new(0.0, 0.0, 0.0);
+            for i in 0..steps {
+                let wavelengths = Wavelengths::hero((i as f32 + 0.5) / steps as f32, hero_only);
+                sum += wavelengths.to_rgb(wavelengths.spectrum(col));
+            }
+            let mean = sum * (1.0 / steps as f32);
+            assert!(close(mean, col, 0.04), "{} came back as {}", col, mean);
+        }
+    }
+
+    // Bluer light bends more
+    let ior = Wavelengths::hero(0.0, false).glass_ior();
+    assert!(ior[0] > ior[1] && ior[1] > ior[2]);
+}
+
+#[test]
+fn spectral_render_matches_rgb() {
+    let sphere = "sphere { pos 0 10 0\n radius 2\n material { color 0.9 0.7 0.1 } }";
+    let render = |spectral: bool| {
+        let scene = scene("aperture_radius 0", Col::new(0.3, 0.6, 0.9), sphere);
+        let settings = RenderSettings {
+            max_bounces: 2,
+            spectral,
+            ..settings(8, 8)
+        };
+        let image = Renderer::new(scene, settings).aov(Pass::Beauty, 256);
+        image.iter().fold(Col::new(0.0, 0.0, 0.0), |sum, col| sum + *col) * (1.0 / image.len() as f32)
+    };
+
+    let (rgb, spectral) = (render(false), render(true));
+    assert!(close(rgb, spectral, 0.05), "rgb {} spectral {}", rgb, spectral);
+}
@@ -0,0 +1,91 @@
+// Stereo cameras: each eye renders its half of the image from its own side of the camera.
+
+mod common;
+
+use common::{gray, scene};
+use simple_rust_cpu_raytracer::scene::Stereo;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, Pass};
+use std::path::Path;
+
+const EYE_SIZE: usize = 20;
+
+// Render a black sphere 10 units in front of a stereo camera, against a white sky
+fn render(projection: &str, stereo: &str, interocular_distance: f32, convergence: f32) -> Vec<Col> {
+    let camera = format!(
+        "aperture_radius 0\n projection {}\n stereo {}\n interocular_distance {}\n convergence {}",
+        projection, stereo, interocular_distance, convergence
+    );
+    let sphere = "sphere { pos 0 10 0\n radius 1\n material { color 0 0 0 } }";
+    let scene = scene(&camera, gray(1.0), sphere);
+    let (width, height) = match scene.camera().stereo {
+        Stereo::Mono => (EYE_SIZE, EYE_SIZE),
+        Stereo::SideBySide => (EYE_SIZE * 2, EYE_SIZE),
+        Stereo::TopBottom => (EYE_SIZE, EYE_SIZE * 2),
+    };
+    common::renderer(scene, width, height).aov(Pass::Beauty, 4)
+}
+
+// The mean column of the sphere's pixels in a part of the image
+fn sphere_column(image: &[Col], width: usize, columns: std::ops::Range<usize>) -> f32 {
+    let (mut sum, mut count) = (0.0, 0.0);
+    for (i, col) in image.iter().enumerate() {
+        let x = i % width;
+        if columns.contains(&x) && col.r < 0.5 {
+            sum += (x - columns.start) as f32;
+            count += 1.0;
+        }
+    }
+    assert!(count > 0.0, "the sphere is not in view");
+    sum / count
+}
+
+#[test]
+fn eyes_see_the_sphere_from_either_side() {
+    let width = EYE_SIZE * 2;
+    let column = |image: &[Col]| {
+        (
+            sphere_column(image, width, 0..EYE_SIZE),
+            sphere_column(image, width, EYE_SIZE..width),
+        )
+    };
+
+    // Without any distance between them, both eyes see what a mono camera sees
+    let (left, right) = column(&render("perspective", "side_by_side", 0.0, 0.0));
+    let mono = sphere_column(&render("perspective", "mono", 0.0, 0.0), EYE_SIZE, 0..EYE_SIZE);
+    assert!((left - mono).abs() < 1.0 && (right - mono).abs() < 1.0);
+
+    // Parallel eyes see a near sphere shifted towards the other eye
+    let (left, right) = column(&render("perspective", "side_by_side", 2.0, 0.0));
+    assert!(left - right > 2.0, "left {} right {}", left, right);
+
+    // Converged on the sphere, the eyes see it in the same place again
+    let (left, right) = column(&render("perspective", "side_by_side", 2.0, 10.0));
+    assert!((left - right).abs() < 1.0, "left {} right {}", left, right);
+}
+
+#[test]
+fn converged_eyes_line_up_in_every_projection() {
+    let width = EYE_SIZE * 2;
+    for projection in ["orthographic", "fisheye"] {
+        let image = render(projection, "side_by_side", 2.0, 10.0);
+        let left = sphere_column(&image, width, 0..EYE_SIZE);
+        let right = sphere_column(&image, width, EYE_SIZE..width);
+        assert!((left - right).abs() < 1.0, "{}: left {} right {}", projection, left, right);
+    }
+}
+
+#[test]
+fn top_bottom_stacks_the_eyes() {
+    let image = render("perspective", "top_bottom", 2.0, 0.0);
+    let (top, bottom) = image.split_at(EYE_SIZE * EYE_SIZE);
+    let left = sphere_column(top, EYE_SIZE, 0..EYE_SIZE);
+    let right = sphere_column(bottom, EYE_SIZE, 0..EYE_SIZE);
+    assert!(left - right > 2.0, "left {} right {}", left, right);
+}
+
+#[test]
+fn stereo_modes_are_parsed_by_name() {
+    assert_eq!(Stereo::from_name(Stereo::TopBottom.name()), Some(Stereo::TopBottom));
+    assert!(parse_scene("camera { stereo anaglyph }", Path::new("."), 1).is_err());
+}
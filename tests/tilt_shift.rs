@@ -0,0 +1,118 @@
+// Tilt-shift lenses: shifting the lens moves the picture without changing the perspective,
+// and tilting it brings a plane of focus that leans away from the camera into focus.
+
+mod common;
+
+use common::{gray, moments};
+use simple_rust_cpu_raytracer::scene::Scene;
+use simple_rust_cpu_raytracer::scene_file::parse_scene;
+use simple_rust_cpu_raytracer::{Col, Pass, Renderer};
+use std::path::Path;
+
+const SIZE: usize = 40;
+
+// How far the camera looks up, so that only the sky is behind the objects, even through the
+// edges of a wide aperture
+const LOOK_UP: f32 = 1.2;
+
+// Where a point in camera space, x to the right, y forward and z up, is in the scene
+fn turned_up(x: f32, y: f32, z: f32) -> (f32, f32, f32) {
+    let (sin, cos) = LOOK_UP.sin_cos();
+    (x, y * cos - z * sin, y * sin + z * cos)
+}
+
+fn scene(camera: &str, sky: f32, objects: &[(f32, f32, f32, f32, &str)]) -> Scene {
+    let mut spheres = String::new();
+    for &(x, y, z, radius, material) in objects {
+        let (x, y, z) = turned_up(x, y, z);
+        spheres += &format!(
+            "sphere {{ pos {} {} {}\n radius {}\n material {{ {} }} }}\n",
+            x, y, z, radius, material
+        );
+    }
+    common::scene(&format!("rot {} 0 0\n {}", LOOK_UP, camera), gray(sky), &spheres)
+}
+
+fn renderer(scene: Scene) -> Renderer {
+    common::renderer(scene, SIZE, SIZE)
+}
+
+// The mean position of the rows from start to end, weighted by a value for each pixel, and
+// how far the weight is spread around it, as (x, y, standard deviation)
+fn footprint(image: &[Col], rows: std::ops::Range<usize>, weight: impl Fn(Col) -> f32) -> (f32, f32, f32) {
+    let (x, y, variance_x, variance_y) = moments(&image[rows.start * SIZE..rows.end * SIZE], SIZE, weight);
+    (x, y + rows.start as f32, (variance_x + variance_y).max(0.0).sqrt())
+}
+
+#[test]
+fn shifted_lenses_move_the_picture_and_the_overlays() {
+    let black = "color 0 0 0";
+    let objects = [(0.0, 10.0, 0.0, 1.0, black)];
+    let dark = |col: Col| 1.0 - col.r;
+
+    let image = renderer(scene("aperture_radius 0", 1.0, &objects)).aov(Pass::Beauty, 4);
+    let (x, y, spread) = footprint(&image, 0..SIZE, dark);
+
+    // Shifted up by a quarter of the image height, the sphere moves down by as much, and
+    // stays the same size, as the view direction doesn't change
+    let shifted = scene("aperture_radius 0\n shift 0 0.25", 1.0, &objects);
+    let mut overlay = vec![0; SIZE * SIZE + 1];
+    let (_, y_middle, z_middle) = turned_up(0.0, 10.0, 0.0);
+    let wireframe = format!(
+        "camera {{ pos 0 0 0 }}\n wireframe {{ line {{ from -0.1 {y} {z}\n to 0.1 {y} {z} }} }}",
+        y = y_middle,
+        z = z_middle
+    );
+    let lines = parse_scene(&wireframe, Path::new("."), 1).unwrap().wireframes;
+    lines[0].render(&mut overlay, shifted.camera(), SIZE, SIZE);
+
+    let image = renderer(shifted).aov(Pass::Beauty, 4);
+    let (shifted_x, shifted_y, shifted_spread) = footprint(&image, 0..SIZE, dark);
+    assert!((shifted_x - x).abs() < 0.5, "{} instead of {}", shifted_x, x);
+    assert!((shifted_y - y - SIZE as f32 * 0.25).abs() < 0.5, "{} instead of {}", shifted_y, y);
+    assert!((shifted_spread - spread).abs() < 0.5, "{} instead of {}", shifted_spread, spread);
+
+    let line_y = overlay[..SIZE * SIZE].iter().position(|&pixel| pixel != 0).unwrap() / SIZE;
+    assert!((line_y as f32 - shifted_y).abs() < 1.5, "overlay at {}, sphere at {}", line_y, shifted_y);
+}
+
+#[test]
+fn tilted_lenses_focus_on_a_leaning_plane() {
+    // Two lights, one near and low and one far and high, both on the plane of focus leaning
+    // back by atan(1.5): y = 10 + 1.5 z
+    let light = "color 0 0 0\n emission_color 1 1 1\n emission_intensity 10";
+    let objects = [(0.0, 7.0, -2.0, 0.2, light), (0.0, 16.0, 4.0, 0.2, light)];
+    let spreads = |tilt: f32| {
+        let camera = format!("focal_length 10\n aperture_radius 2\n tilt {} 0", tilt);
+        let image = renderer(scene(&camera, 0.0, &objects)).aov(Pass::Beauty, 64);
+        let brightness = |col: Col| col.r;
+        let (_, _, far) = footprint(&image, 0..SIZE / 2, brightness);
+        let (_, _, near) = footprint(&image, SIZE / 2..SIZE, brightness);
+        (far, near)
+    };
+
+    // Focused at 10, both are out of focus, and tilted both are much sharper
+    let (far, near) = spreads(0.0);
+    assert!(far > 2.5 && near > 2.5, "{} {}", far, near);
+    let (tilted_far, tilted_near) = spreads(1.5f32.atan().to_degrees());
+    assert!(tilted_far < far / 2.0 && tilted_near < near / 2.0, "{} {}", tilted_far, tilted_near);
+}
+
+#[test]
+fn tilts_must_be_less_than_a_right_angle() {
+    let error = parse_scene("camera { tilt 90 0 }", Path::new("."), 1).unwrap_err();
+    assert!(error.to_string().contains("less than"));
+}
+
+#[test]
+fn autofocus_puts_the_plane_of_focus_through_what_is_in_the_middle() {
+    // The middle of an image shifted up looks up through the lens, at the sphere. The plane
+    // of focus goes through the near side of the sphere, at that depth.
+    let up = 0.2 * 2.0 * 30f32.to_radians().tan() * 10.0;
+    let objects = [(0.0, 10.0, up, 1.0, "color 0 0 0")];
+    let mut renderer = renderer(scene("aperture_radius 0.5\n shift 0 0.2", 1.0, &objects));
+    renderer.autofocus();
+    let distance = 10f32.hypot(up);
+    let depth = (distance - 1.0) * 10.0 / distance;
+    assert!((renderer.camera().focal_length - depth).abs() < 0.05, "{} instead of {}", renderer.camera().focal_length, depth);
+}